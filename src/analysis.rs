@@ -0,0 +1,285 @@
+// Static analysis of a rom: which of its instructions can be reached from
+// the load address, where control goes from each, and what is known about
+// the registers on the way in. Lint and the control flow graph are built on
+// it.
+//
+// The walk follows every path as if any skip could go either way and every
+// call returns to the instruction after it. Registers and I are tracked as
+// constants where the code makes them so (6XNN, ANNN, 7XNN on a known value,
+// ...) and forgotten where paths with different values meet, or after a call,
+// which could change anything. BNNN jumps can't be followed without running
+// the rom, so the code they lead to counts as unreachable.
+
+use std::collections::BTreeMap;
+
+use crate::disasm;
+
+// the registers and I where they're known
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct State
+{
+    pub v: [Option<u8>; 16],
+    pub i: Option<u16>,
+}
+
+impl State
+{
+    pub fn unknown() -> State
+    {
+        State { v: [None; 16], i: None }
+    }
+
+    // what's known on both ways in
+    fn merge(&self, other: &State) -> State
+    {
+        let mut merged = *self;
+        for (v, other) in merged.v.iter_mut().zip(other.v.iter())
+        {
+            if *v != *other
+            {
+                *v = None;
+            }
+        }
+        if merged.i != other.i
+        {
+            merged.i = None;
+        }
+        merged
+    }
+}
+
+// how control gets from one instruction to another
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Edge
+{
+    Next, // the following instruction
+    Skip, // the one after that, when a skip is taken
+    Jump, // 1NNN
+    Call, // 2NNN, into the subroutine
+    Return, // past a 2NNN, once the subroutine returns
+}
+
+pub struct Analysis
+{
+    pub load_address: u16,
+    pub end: u16,                                    // first address past the rom
+    pub states: BTreeMap<u16, State>,                // reachable instructions and the state they start in
+    pub successors: BTreeMap<u16, Vec<(u16, Edge)>>, // where each one goes, targets outside the rom included
+    pub indirect: Vec<u16>,                          // BNNN jumps, which go who knows where
+    pub invalid: Vec<u16>,                           // opcodes that aren't instructions
+}
+
+impl Analysis
+{
+    pub fn opcode(bytes: &[u8], load_address: u16, address: u16) -> Option<u16>
+    {
+        let offset = (address as usize).checked_sub(load_address as usize)?;
+        match bytes.get(offset..offset + 2)?
+        {
+            &[high, low] => Some((high as u16) << 8 | low as u16),
+            _ => None,
+        }
+    }
+
+    pub fn in_rom(&self, address: u16) -> bool
+    {
+        address >= self.load_address && address + 1 < self.end
+    }
+
+    // the bytes of the reachable instructions
+    pub fn code_bytes(&self) -> Vec<bool>
+    {
+        let mut code = vec![false; 4096];
+        for &address in self.states.keys()
+        {
+            code[address as usize] = true;
+            code[address as usize + 1] = true;
+        }
+        code
+    }
+
+    // the reachable code cut into basic blocks, by their first address: a
+    // block starts where anything but the instruction before it leads, and
+    // ends at whatever goes anywhere but to the next instruction
+    pub fn blocks(&self) -> BTreeMap<u16, Vec<u16>>
+    {
+        let mut predecessors: BTreeMap<u16, usize> = BTreeMap::new();
+        let mut leaders = vec![self.load_address];
+        for successors in self.successors.values()
+        {
+            for &(to, edge) in successors
+            {
+                *predecessors.entry(to).or_insert(0) += 1;
+                if edge != Edge::Next || successors.len() > 1
+                {
+                    leaders.push(to);
+                }
+            }
+        }
+        leaders.extend(predecessors.iter().filter(|&(_, &count)| count > 1).map(|(&to, _)| to));
+        leaders.retain(|address| self.states.contains_key(address));
+        leaders.sort_unstable();
+        leaders.dedup();
+
+        let mut blocks = BTreeMap::new();
+        for &leader in &leaders
+        {
+            let mut block = vec![leader];
+            let mut address = leader;
+            while let Some(&[(next, Edge::Next)]) = self.successors.get(&address).map(|s| s.as_slice())
+            {
+                if !self.states.contains_key(&next) || leaders.binary_search(&next).is_ok()
+                {
+                    break;
+                }
+                block.push(next);
+                address = next;
+            }
+            blocks.insert(leader, block);
+        }
+        blocks
+    }
+
+    // the 00EEs a subroutine can return through, found by following it
+    // without going into what it calls
+    pub fn returns(&self, bytes: &[u8], entry: u16) -> Vec<u16>
+    {
+        let mut returns = Vec::new();
+        let mut seen = vec![entry];
+        let mut pending = vec![entry];
+        while let Some(address) = pending.pop()
+        {
+            if Analysis::opcode(bytes, self.load_address, address) == Some(0x00EE)
+            {
+                returns.push(address);
+            }
+            for &(to, edge) in self.successors.get(&address).into_iter().flatten()
+            {
+                if edge != Edge::Call && self.states.contains_key(&to) && !seen.contains(&to)
+                {
+                    seen.push(to);
+                    pending.push(to);
+                }
+            }
+        }
+        returns.sort_unstable();
+        returns
+    }
+}
+
+// what an instruction does to the state, None where a value can't be known
+fn transfer(opcode: u16, state: &State) -> State
+{
+    let mut out = *state;
+    let x = ((opcode & 0x0F00) >> 8) as usize;
+    let y = ((opcode & 0x00F0) >> 4) as usize;
+    let nn = (opcode & 0x00FF) as u8;
+    let nnn = opcode & 0x0FFF;
+    let (vx, vy) = (state.v[x], state.v[y]);
+    let both = |f: fn(u8, u8) -> u8| vx.zip(vy).map(|(a, b)| f(a, b));
+
+    match disasm::pattern(opcode)
+    {
+        "6XNN" => out.v[x] = Some(nn),
+        "7XNN" => out.v[x] = vx.map(|v| v.wrapping_add(nn)),
+        "8XY0" => out.v[x] = vy,
+        "8XY1" => { out.v[x] = both(|a, b| a | b); out.v[0xF] = None; },
+        "8XY2" => { out.v[x] = both(|a, b| a & b); out.v[0xF] = None; },
+        "8XY3" => { out.v[x] = both(|a, b| a ^ b); out.v[0xF] = None; },
+        "8XY4" => { out.v[x] = both(|a, b| a.wrapping_add(b)); out.v[0xF] = None; },
+        "8XY5" => { out.v[x] = both(|a, b| a.wrapping_sub(b)); out.v[0xF] = None; },
+        "8XY7" => { out.v[x] = both(|a, b| b.wrapping_sub(a)); out.v[0xF] = None; },
+        // what's shifted depends on a quirk
+        "8XY6" | "8XYE" => { out.v[x] = None; out.v[0xF] = None; },
+        "CXNN" | "FX07" | "FX0A" => out.v[x] = None,
+        "DXYN" => out.v[0xF] = None,
+        "ANNN" => out.i = Some(nnn),
+        "FX1E" => out.i = state.i.zip(vx).map(|(i, v)| i.wrapping_add(v as u16)),
+        "FX29" => out.i = None,
+        // where I ends up depends on a quirk
+        "FX55" => out.i = None,
+        "FX65" =>
+        {
+            for v in out.v.iter_mut().take(x + 1)
+            {
+                *v = None;
+            }
+            out.i = None;
+        },
+        _ => {},
+    }
+    out
+}
+
+pub fn analyze(bytes: &[u8], load_address: u16) -> Analysis
+{
+    let mut analysis = Analysis
+    {
+        load_address,
+        end: (load_address as usize + bytes.len()).min(4096) as u16,
+        states: BTreeMap::new(),
+        successors: BTreeMap::new(),
+        indirect: Vec::new(),
+        invalid: Vec::new(),
+    };
+
+    let mut pending = vec![load_address];
+    analysis.states.insert(load_address, State::unknown());
+    while let Some(address) = pending.pop()
+    {
+        let state = analysis.states[&address];
+        let opcode = match Analysis::opcode(bytes, load_address, address)
+        {
+            Some(opcode) => opcode,
+            None => continue,
+        };
+        let out = transfer(opcode, &state);
+        let nnn = opcode & 0x0FFF;
+        let next = address.wrapping_add(2);
+
+        let successors: Vec<(u16, Edge, State)> = match disasm::pattern(opcode)
+        {
+            "00EE" => Vec::new(),
+            "1NNN" if nnn == address => Vec::new(), // the usual way to stop
+            "1NNN" => vec![(nnn, Edge::Jump, out)],
+            "2NNN" => vec![(nnn, Edge::Call, out), (next, Edge::Return, State::unknown())],
+            "3XNN" | "4XNN" | "5XY0" | "9XY0" | "EX9E" | "EXA1" =>
+            {
+                vec![(next, Edge::Next, out), (next.wrapping_add(2), Edge::Skip, out)]
+            },
+            "BNNN" =>
+            {
+                analysis.indirect.push(address);
+                Vec::new()
+            },
+            "data" =>
+            {
+                analysis.invalid.push(address);
+                Vec::new()
+            },
+            _ => vec![(next, Edge::Next, out)],
+        };
+
+        analysis.successors.insert(address, successors.iter().map(|&(to, edge, _)| (to, edge)).collect());
+        for (to, _, state) in successors
+        {
+            if Analysis::opcode(bytes, load_address, to).is_none()
+            {
+                continue;
+            }
+            let merged = match analysis.states.get(&to)
+            {
+                Some(known) => known.merge(&state),
+                None => state,
+            };
+            if analysis.states.get(&to) != Some(&merged)
+            {
+                analysis.states.insert(to, merged);
+                pending.push(to);
+            }
+        }
+    }
+    analysis.indirect.sort_unstable();
+    analysis.invalid.sort_unstable();
+    analysis
+}
@@ -0,0 +1,111 @@
+// Synthesizes the CHIP-8 beeper as a plain square wave and writes it to WAV
+// files, for captures that need sound without an audio device.
+
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const SAMPLE_RATE: u32 = 44_100;
+const TONE_HZ: u32 = 440;
+
+pub struct Beeper
+{
+    volume: f32,
+    phase: u32, // position inside the current wave period, in samples * TONE_HZ
+}
+
+impl Beeper
+{
+    pub fn new(volume: f32) -> Beeper
+    {
+        Beeper
+        {
+            volume,
+            phase: 0,
+        }
+    }
+
+    // fills `out` with the beeper output; the phase carries over between calls so
+    // that back to back frames of sound join without clicks
+    pub fn synthesize(& mut self, on: bool, out: &mut [i16])
+    {
+        let amplitude = (i16::MAX as f32 * self.volume) as i16;
+        for sample in out.iter_mut()
+        {
+            *sample = if !on
+            {
+                0
+            }
+            else if self.phase < SAMPLE_RATE / 2
+            {
+                amplitude
+            }
+            else
+            {
+                -amplitude
+            };
+            self.phase = (self.phase + TONE_HZ) % SAMPLE_RATE;
+        }
+    }
+}
+
+// 16 bit mono PCM. The sizes in the header are only known at the end, so they
+// are patched in by `finish`.
+pub struct WavWriter
+{
+    file: BufWriter<File>,
+    samples: u32,
+}
+
+impl WavWriter
+{
+    pub fn create(path: &Path) -> io::Result<WavWriter>
+    {
+        let mut wav = WavWriter
+        {
+            file: BufWriter::new(File::create(path)?),
+            samples: 0,
+        };
+        wav.write_header()?;
+        Ok(wav)
+    }
+
+    pub fn write(& mut self, samples: &[i16]) -> io::Result<()>
+    {
+        for sample in samples
+        {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()>
+    {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.file.flush()
+    }
+
+    fn write_header(& mut self) -> io::Result<()>
+    {
+        let data_size = self.samples * 2;
+
+        self.file.write_all(b"RIFF")?;
+        self.file.write_all(&(36 + data_size).to_le_bytes())?;
+        self.file.write_all(b"WAVE")?;
+
+        self.file.write_all(b"fmt ")?;
+        self.file.write_all(&16u32.to_le_bytes())?;              // chunk size
+        self.file.write_all(&1u16.to_le_bytes())?;               // PCM
+        self.file.write_all(&1u16.to_le_bytes())?;               // mono
+        self.file.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        self.file.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;  // byte rate
+        self.file.write_all(&2u16.to_le_bytes())?;               // block align
+        self.file.write_all(&16u16.to_le_bytes())?;              // bits per sample
+
+        self.file.write_all(b"data")?;
+        self.file.write_all(&data_size.to_le_bytes())
+    }
+}
@@ -0,0 +1,96 @@
+// Octo cartridges: gif images with an Octo program and its run options hidden
+// in the palette indices. Every pixel carries four bits of payload in the low
+// nibble of its color index, high nibble first, going through the frames in
+// order. The payload is a 32 bit big endian length followed by that many
+// bytes of json:
+//
+//   { "program": "<octo source>", "options": { "tickrate": 20, ... } }
+
+use std::io::Cursor;
+
+use serde_json::Value;
+
+use crate::cpu::Quirks;
+use crate::octo;
+use crate::palette::Palette;
+use crate::romdb::RomInfo;
+
+pub struct Cartridge
+{
+    pub program: Vec<u8>,
+    pub info: RomInfo, // the run options that have an equivalent here
+}
+
+pub fn is_cartridge(name: &str) -> bool
+{
+    name.to_lowercase().ends_with(".gif")
+}
+
+fn payload(gif: &[u8]) -> Result<Vec<u8>, String>
+{
+    let mut decoder = gif::DecodeOptions::new().read_info(Cursor::new(gif)).map_err(|e| e.to_string())?;
+    let mut nibbles = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().map_err(|e| e.to_string())?
+    {
+        nibbles.extend(frame.buffer.iter().map(|&index| index & 0xF));
+    }
+
+    let bytes: Vec<u8> = nibbles.chunks_exact(2).map(|pair| pair[0] << 4 | pair[1]).collect();
+    if bytes.len() < 4
+    {
+        return Err("not an octo cartridge".to_string());
+    }
+    let size = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    match bytes.get(4..4 + size)
+    {
+        Some(payload) => Ok(payload.to_vec()),
+        None => Err("not an octo cartridge".to_string()),
+    }
+}
+
+// "#FF8800" as used by octo for colors
+fn color(options: &Value, name: &str) -> Option<String>
+{
+    let text = options.get(name)?.as_str()?.trim_start_matches('#');
+    Some(text.to_string())
+}
+
+fn options_info(options: &Value) -> RomInfo
+{
+    let flag = |name: &str| options.get(name).and_then(Value::as_bool).unwrap_or(false);
+
+    let quirks = Quirks
+    {
+        shift_vy: !flag("shiftQuirks"),
+        load_store_increment_i: !flag("loadStoreQuirks"),
+        jump_vx: flag("jumpQuirks"),
+        vf_reset: flag("logicQuirks"),
+    };
+    let palette = match (color(options, "backgroundColor"), color(options, "fillColor"))
+    {
+        (Some(background), Some(fill)) => Palette::parse(&format!("{},{}", background, fill)),
+        _ => None,
+    };
+
+    RomInfo
+    {
+        cycles_per_frame: options.get("tickrate").and_then(Value::as_u64).filter(|&n| n > 0).map(|n| n as u32),
+        quirks: Some(quirks),
+        palette,
+        ..RomInfo::default()
+    }
+}
+
+pub fn read(gif: &[u8]) -> Result<Cartridge, String>
+{
+    let payload = payload(gif)?;
+    let json: Value = serde_json::from_slice(&payload).map_err(|e| format!("bad cartridge data: {}", e))?;
+    let source = json.get("program").and_then(Value::as_str).ok_or_else(|| "the cartridge has no program".to_string())?;
+    let program = octo::assemble(source)?;
+
+    Ok(Cartridge
+    {
+        program,
+        info: json.get("options").map(options_info).unwrap_or_default(),
+    })
+}
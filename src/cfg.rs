@@ -0,0 +1,129 @@
+// The cfg subcommand: the code reachable from the load address as a graph of
+// basic blocks in Graphviz DOT, for finding one's way around a rom. Edges are
+// labelled with how control gets there: a jump, a call, the skip of a skip
+// instruction, or the return from a subroutine, which goes back to after
+// every call of it. BNNN jumps go to a red "?" as where they lead isn't
+// known without running the rom.
+//
+//   rusty-8 cfg invaders.c8 > invaders.dot && dot -Tsvg invaders.dot > invaders.svg
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use crate::analysis::{Analysis, Edge};
+use crate::disasm;
+
+fn quote(text: &str) -> String
+{
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn node(address: u16) -> String
+{
+    format!("b{:03X}", address)
+}
+
+pub fn write_dot(out: &mut dyn Write, analysis: &Analysis, bytes: &[u8], name: &str) -> io::Result<()>
+{
+    let blocks = analysis.blocks();
+    let opcode = |address: u16| Analysis::opcode(bytes, analysis.load_address, address).unwrap_or(0);
+
+    // the call sites of each subroutine, to return to
+    let mut calls: BTreeMap<u16, Vec<u16>> = BTreeMap::new();
+    for (&from, successors) in &analysis.successors
+    {
+        for &(to, edge) in successors
+        {
+            if edge == Edge::Call
+            {
+                calls.entry(to).or_default().push(from);
+            }
+        }
+    }
+
+    writeln!(out, "digraph {} {{", quote(name))?;
+    writeln!(out, "    node [shape=box, fontname=\"monospace\"];")?;
+    writeln!(out, "    edge [fontname=\"monospace\", fontsize=10];")?;
+
+    let mut outside = BTreeSet::new();
+    for (&start, block) in &blocks
+    {
+        let heading = if start == analysis.load_address
+        {
+            "start\\l".to_string()
+        }
+        else if calls.contains_key(&start)
+        {
+            format!("sub_{:03X}\\l", start)
+        }
+        else
+        {
+            String::new()
+        };
+        let lines: String = block.iter()
+            .map(|&a| format!("{:03X}: {:04X}  {}\\l", a, opcode(a), disasm::disassemble(opcode(a))))
+            .collect();
+        let last = *block.last().unwrap();
+        let style = if analysis.invalid.contains(&last) { ", color=red" } else { "" };
+        writeln!(out, "    {} [label=\"{}{}\"{}];", node(start), heading, lines, style)?;
+
+        for &(to, edge) in analysis.successors.get(&last).into_iter().flatten()
+        {
+            let attributes = match edge
+            {
+                Edge::Next => "",
+                Edge::Skip => " [label=\"skip\", style=dashed]",
+                Edge::Jump => " [label=\"jump\"]",
+                Edge::Call => " [label=\"call\", color=blue]",
+                // drawn from the subroutine's returns instead, when it has any
+                Edge::Return if !analysis.returns(bytes, opcode(last) & 0x0FFF).is_empty() => continue,
+                Edge::Return => " [label=\"no return found\", style=dotted]",
+            };
+            let target = if analysis.states.contains_key(&to)
+            {
+                node(to)
+            }
+            else
+            {
+                outside.insert(to);
+                format!("outside_{:03X}", to)
+            };
+            writeln!(out, "    {} -> {}{};", node(start), target, attributes)?;
+        }
+
+        if analysis.indirect.contains(&last)
+        {
+            writeln!(out, "    unresolved_{:03X} [label=\"?\", shape=circle, color=red];", last)?;
+            writeln!(out, "    {} -> unresolved_{:03X} [label=\"unresolved\", style=dashed, color=red];", node(start), last)?;
+        }
+    }
+
+    // returns, from each 00EE of a subroutine to after every call of it
+    let owner: BTreeMap<u16, u16> = blocks.iter().flat_map(|(&start, block)| block.iter().map(move |&a| (a, start))).collect();
+    let mut returns = BTreeSet::new();
+    for (&entry, sites) in &calls
+    {
+        for ret in analysis.returns(bytes, entry)
+        {
+            for &site in sites
+            {
+                let after = site.wrapping_add(2);
+                if analysis.states.contains_key(&after)
+                {
+                    returns.insert((owner[&ret], after));
+                }
+            }
+        }
+    }
+    for (from, to) in returns
+    {
+        writeln!(out, "    {} -> {} [label=\"return\", style=dotted, color=darkgreen];", node(from), node(to))?;
+    }
+
+    for address in outside
+    {
+        writeln!(out, "    outside_{:03X} [label=\"0x{:03X}\\noutside the rom\", shape=plaintext];", address, address)?;
+    }
+    writeln!(out, "}}")?;
+    out.flush()
+}
@@ -0,0 +1,525 @@
+// Command line parsing. Options can be given as "--name value" or
+// "--name=value"; running a rom is the default when no subcommand is given.
+
+use std::ops::RangeInclusive;
+
+use crate::cpu::{self, Quirks};
+use crate::keymap::Keymap;
+use crate::palette::Palette;
+use crate::scale::ScaleMode;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub const USAGE: &str = "\
+rusty-8, a CHIP-8 emulator
+
+usage:
+    rusty-8 [run] [options] [<rom_file> | <directory>]
+    rusty-8 disasm [--load-address ADDR] <rom_file>
+    rusty-8 info [--rom-db FILE] [--load-address ADDR] <rom_file>
+    rusty-8 lint [--rom-db FILE] [--load-address ADDR] <rom_file>
+    rusty-8 cfg [--load-address ADDR] <rom_file> > graph.dot
+    rusty-8 bench [--ipf N] [--quirks NAME] [--seed N] [--rom-db FILE] [--load-address ADDR]
+                  [--frames N] <rom_file>
+    rusty-8 tui [--ipf N] [--quirks NAME] [--seed N] [--keymap KEYS] [--break ADDR]...
+                [--braille] <rom_file>
+    rusty-8 tracediff [--context N] [--timers] <trace> <trace>
+    rusty-8 -h | --help | -V | --version
+
+    <rom_file> is a file, - for standard input, a zip archive (which asks for
+    one of its roms if it has several, info lists them) or archive.zip:path/rom.ch8.
+    Octo cartridges (.gif) are assembled and run with the options they carry.
+
+    Without a rom, or with a directory, a launcher lists the roms there and the
+    recently played ones. Escape in a game goes back to it.
+
+    Keys in the window:
+        F5, shift+F5        reset and reload the rom, clearing memory as well
+        pause or F6         pause and resume
+        F7, shift+F7        advance a frame, or an instruction, while paused
+        tab                 fast forward while held
+        F8                  slow motion (quarter speed) on and off
+        F3                  frame rate, instructions per second and speed
+        F9                  debug window
+        F10                 gif recording on and off
+        F11                 instruction tracing on and off, with --trace
+        F12, shift+F12      screenshot at the window scale, or at 64x32
+        alt+enter, escape   fullscreen, back to the launcher
+
+    A rom dropped onto the window replaces the running one. A vm fault stops the
+    game and stays on screen until F5 or the window is closed.
+
+    tui is a debugger in the terminal, for when there is no display. It starts
+    paused: space runs and pauses, enter steps an instruction, F7 a frame, F9
+    sets a breakpoint on the disassembly cursor (up, down, home for pc), page
+    up and down scroll memory, F2 switches to braille and escape quits. F5,
+    tab, F8, F10, F11 and F12 work as with --terminal.
+
+    lint looks through the code reachable from the load address for instructions
+    that depend on quirks, odd or stray jumps, unreachable code, writes into the
+    font area or over the code, and sprites drawn off the screen, and recommends
+    the quirk preset the code looks written for.
+
+    cfg writes the code reachable from the load address as a Graphviz graph of
+    basic blocks, with edges for jumps, calls, returns and skips. Where BNNN
+    jumps go is unresolved.
+
+    tracediff compares two --trace files, text or binary, instruction by
+    instruction and shows where pc, the opcode, V0-VF, I or SP first differ,
+    with --context N instructions (default 3) around it. --timers compares the
+    delay and sound timers as well. It exits with 1 when the traces differ.
+
+display:
+    --scale N               window size as a multiple of 64x32 (default 10)
+    --stretch               scale to any size instead of whole multiples
+    --fullscreen            start fullscreen (alt+enter toggles)
+    --palette NAME|BG,FG    mono, green, amber, lcd, inverted or two RRGGBB colors
+    --terminal              play in the terminal instead of a window (always, in a
+                            build without sdl): escape quits, F5 resets, F6
+                            pauses, F7 advances a frame (shift+F7 an
+                            instruction), tab switches fast forward, F8 slow
+                            motion, F2 braille, F10 records a gif, F11 traces
+                            and F12 saves a screenshot (shift+F12 at 64x32). A
+                            rom file dropped onto the terminal is run instead.
+    --braille               draw the screen in braille instead of half blocks, for
+                            --terminal and tui
+
+emulation:
+    --ipf N                 instructions per 60 Hz frame (default 10)
+    --quirks NAME           rusty-8, chip8, schip or xochip
+    --seed N                seed for the random number generator
+    --paused                start paused, the pause key resumes
+    --fast-forward N        speed while tab is held (default 4)
+    --debug                 open the debug window with the registers, stack,
+                            memory and disassembly
+    --load-address ADDR     where the rom goes in memory (default 0x200, 0x600
+                            for ETI-660 roms)
+    --rom-db FILE           extra rom database, on top of the built in one and
+                            ~/.config/rusty-8/roms.db
+    --watch                 reload the rom whenever the file changes
+    --watch-keep            the same, but only replace the program and keep the
+                            registers and the rest of memory
+    --break ADDR            tui stops before running the instruction at ADDR
+
+    ipf, quirks, palette and key hints default to the rom database entry of the
+    rom, if it has one, and then to the options of an octo cartridge
+
+input:
+    --keymap NAME|KEYS      colemak, qwerty, azerty, dvorak or 16 keys in keypad order
+    --record-input FILE     record the keypad to a movie file
+    --replay FILE           play a movie file back
+
+sound:
+    --mute
+    --volume N              0 to 100 (default 25)
+
+capture:
+    --headless FRAMES       run this many frames without a window
+    --record FILE           record a gif, F10 toggles recording in the window
+    --screenshot FILE       save a png when the run ends, F12 saves one any time
+                            (shift+F12 at 64x32 instead of the window scale)
+    --video-out FILE|-      stream every frame as y4m (.y4m or stdout) or raw rgb24
+    --video-scale N         size of the video stream as a multiple of 64x32 (default 1)
+    --audio-out FILE        beeper wav for the video stream (default next to it)
+    --trace FILE|-          log the registers before every instruction, as text
+                            or, for a .bin file, as 34 byte binary records
+    --trace-range ADDR-ADDR only trace instructions with pc in this range
+    --trace-paused          start with tracing off, for F11 to turn on
+    --profile FILE|-        count the instructions run, by kind, address and
+                            subroutine, and write a report when the run ends
+    --profile-folded FILE|- the subroutine counts as folded stacks, for flamegraphs
+    --coverage FILE         write which addresses ran as code, were read as data
+                            or were written, as JSON, when the run ends
+    --coverage-report FILE|-
+                            the rom's disassembly marked up the same way, as
+                            text or, for a .html file, a page
+
+exit status:
+    0 success, 1 other errors, 2 bad usage, 3 the rom or a trace failed to load,
+    4 the vm faulted
+";
+
+#[derive(Clone)]
+pub struct RunOptions
+{
+    pub rom_file: String,
+
+    pub display_modifier: u32,
+    pub scale_mode: ScaleMode,
+    pub fullscreen: bool,
+    pub palette: Option<Palette>,
+    pub terminal: bool, // play in the terminal
+
+    pub cycles_per_frame: Option<u32>,
+    pub quirks: Option<Quirks>,
+    pub seed: Option<u64>,
+    pub paused: bool,
+    pub fast_forward: u32, // speed multiplier while tab is held
+    pub debug: bool,       // open the debug window
+    pub breakpoints: Vec<u16>,
+    pub braille: bool,
+    pub load_address: u16,
+    pub rom_db: Option<String>,
+    pub watch: bool,      // reload the rom when the file changes
+    pub watch_keep: bool, // and keep the VM state when doing so
+
+    pub keymap: Option<Keymap>,
+    pub record_input: Option<String>,
+    pub replay: Option<String>,
+
+    pub mute: bool,
+    pub volume: u8,
+
+    pub headless: Option<u64>,      // run this many frames without a window
+    pub record: Option<String>,     // record a gif from the first frame
+    pub screenshot: Option<String>, // save a png when the run ends
+    pub video_out: Option<String>,  // stream every frame to this file, "-" for stdout
+    pub video_scale: u32,
+    pub audio_out: Option<String>,  // beeper wav to go with the video stream
+    pub trace: Option<String>,      // log every instruction to this file, "-" for stdout
+    pub trace_range: RangeInclusive<u16>,
+    pub trace_paused: bool,
+    pub profile: Option<String>,        // profile report written at the end
+    pub profile_folded: Option<String>, // and the call tree as folded stacks
+    pub coverage: Option<String>,       // coverage as JSON
+    pub coverage_report: Option<String>, // and as annotated disassembly
+}
+
+pub struct TraceDiffOptions
+{
+    pub traces: [String; 2],
+    pub context: usize, // instructions shown before and after the difference
+    pub timers: bool,   // compare DT and ST too
+}
+
+pub enum Command
+{
+    Run(RunOptions),
+    Disasm(RunOptions), // only rom_file and load_address are used
+    Info(RunOptions),   // only rom_file, load_address and rom_db are used
+    Lint(RunOptions),   // the same
+    Cfg(RunOptions),    // only rom_file and load_address are used
+    Bench(RunOptions),
+    Tui(RunOptions),
+    TraceDiff(TraceDiffOptions),
+    Help,
+    Version,
+}
+
+impl RunOptions
+{
+    fn new() -> RunOptions
+    {
+        RunOptions
+        {
+            rom_file: String::new(),
+            display_modifier: 10,
+            scale_mode: ScaleMode::Integer,
+            fullscreen: false,
+            palette: None,
+            terminal: false,
+            cycles_per_frame: None,
+            quirks: None,
+            seed: None,
+            paused: false,
+            fast_forward: 4,
+            debug: false,
+            breakpoints: Vec::new(),
+            braille: false,
+            load_address: cpu::PROGRAM_START,
+            rom_db: None,
+            watch: false,
+            watch_keep: false,
+            keymap: None,
+            record_input: None,
+            replay: None,
+            mute: false,
+            volume: 25,
+            headless: None,
+            record: None,
+            screenshot: None,
+            video_out: None,
+            video_scale: 1,
+            audio_out: None,
+            trace: None,
+            trace_range: 0..=0xFFF,
+            trace_paused: false,
+            profile: None,
+            profile_folded: None,
+            coverage: None,
+            coverage_report: None,
+        }
+    }
+}
+
+// pulls the value of an option, either glued on with '=' or as the next argument
+fn value(name: &str, inline: Option<String>, args: &mut dyn Iterator<Item = String>) -> Result<String, String>
+{
+    inline.or_else(|| args.next()).ok_or_else(|| format!("{} needs a value", name))
+}
+
+fn number<T: std::str::FromStr>(name: &str, text: &str) -> Result<T, String>
+{
+    text.parse().map_err(|_| format!("{} expects a number, got '{}'", name, text))
+}
+
+// an address in memory, in hex with a 0x prefix or in decimal
+fn address(name: &str, text: &str) -> Result<u16, String>
+{
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X"))
+    {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    };
+    parsed.filter(|&a| a < 0x1000).ok_or_else(|| format!("{} expects an address below 0x1000, got '{}'", name, text))
+}
+
+// two addresses with a dash between them, both included
+fn address_range(name: &str, text: &str) -> Result<RangeInclusive<u16>, String>
+{
+    let (start, end) = text.split_once('-').ok_or_else(|| format!("{} expects ADDR-ADDR, got '{}'", name, text))?;
+    let (start, end) = (address(name, start)?, address(name, end)?);
+    if start > end
+    {
+        return Err(format!("{} starts after it ends, in '{}'", name, text));
+    }
+    Ok(start..=end)
+}
+
+fn positive(name: &str, text: &str) -> Result<u32, String>
+{
+    match number(name, text)?
+    {
+        0 => Err(format!("{} must be at least 1", name)),
+        n => Ok(n),
+    }
+}
+
+// tracediff takes two files rather than a rom, and options of its own
+fn parse_tracediff(mut args: impl Iterator<Item = String>) -> Result<Command, String>
+{
+    let mut traces = Vec::new();
+    let mut context = 3;
+    let mut timers = false;
+
+    while let Some(arg) = args.next()
+    {
+        let (name, inline) = match arg.find('=')
+        {
+            Some(i) if arg.starts_with("--") => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+            _ => (arg.clone(), None),
+        };
+        match name.as_str()
+        {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--context" => context = number(&name, &value(&name, inline, &mut args)?)?,
+            "--timers" => timers = true,
+            _ if name.starts_with('-') => return Err(format!("unknown option '{}' for tracediff", name)),
+            _ => traces.push(arg),
+        }
+    }
+
+    match traces.as_slice()
+    {
+        [a, b] => Ok(Command::TraceDiff(TraceDiffOptions { traces: [a.clone(), b.clone()], context, timers })),
+        _ => Err("tracediff needs two trace files".to_string()),
+    }
+}
+
+pub fn parse(args: Vec<String>) -> Result<Command, String>
+{
+    let mut args = args.into_iter().peekable();
+
+    let subcommand = match args.peek().map(|s| s.as_str())
+    {
+        Some("run") | Some("disasm") | Some("info") | Some("lint") | Some("cfg") | Some("bench") | Some("tui") => args.next().unwrap(),
+        Some("tracediff") =>
+        {
+            args.next();
+            return parse_tracediff(args);
+        },
+        _ => "run".to_string(),
+    };
+
+    let mut options = RunOptions::new();
+    let mut rom_file = None;
+
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "-h" => return Ok(Command::Help),
+            "-V" => return Ok(Command::Version),
+            _ => {},
+        }
+
+        if arg.starts_with('-') && !arg.starts_with("--") && arg != "-"
+        {
+            return Err(format!("unknown option '{}'", arg));
+        }
+        if !arg.starts_with("--")
+        {
+            if rom_file.is_some()
+            {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+            rom_file = Some(arg);
+            continue;
+        }
+
+        let (name, inline) = match arg.find('=')
+        {
+            Some(i) => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+            None => (arg.clone(), None),
+        };
+        let name = name.as_str();
+
+        // disasm, info, lint and cfg only look at the rom file, bench and tui
+        // only at what affects emulation
+        let takes_options = subcommand == "run";
+        let emulates = takes_options || subcommand == "bench" || subcommand == "tui";
+
+        match name
+        {
+            "--help" => return Ok(Command::Help),
+            "--version" => return Ok(Command::Version),
+
+            "--scale" if takes_options => options.display_modifier = positive(name, &value(name, inline, &mut args)?)?,
+            "--stretch" if takes_options => options.scale_mode = ScaleMode::Stretch,
+            "--fullscreen" if takes_options => options.fullscreen = true,
+            "--palette" if takes_options =>
+            {
+                let text = value(name, inline, &mut args)?;
+                options.palette = Some(Palette::parse(&text).ok_or_else(|| format!("unknown palette '{}', \
+                    use one of {} or two RRGGBB colors", text, Palette::PRESETS.join(", ")))?);
+            },
+
+            "--ipf" if emulates => options.cycles_per_frame = Some(positive(name, &value(name, inline, &mut args)?)?),
+            "--quirks" if emulates =>
+            {
+                let text = value(name, inline, &mut args)?;
+                options.quirks = Some(Quirks::preset(&text).ok_or_else(|| format!("unknown quirk preset '{}', \
+                    use one of {}", text, Quirks::PRESETS.join(", ")))?);
+            },
+            "--seed" if emulates => options.seed = Some(number(name, &value(name, inline, &mut args)?)?),
+            "--paused" if takes_options => options.paused = true,
+            "--debug" if subcommand == "run" => options.debug = true,
+            "--fast-forward" if subcommand == "run" => options.fast_forward = positive(name, &value(name, inline, &mut args)?)?,
+            "--load-address" => options.load_address = address(name, &value(name, inline, &mut args)?)?,
+            "--watch" if subcommand == "run" => options.watch = true,
+            "--watch-keep" if subcommand == "run" =>
+            {
+                options.watch = true;
+                options.watch_keep = true;
+            },
+            "--break" if subcommand == "tui" => options.breakpoints.push(address(name, &value(name, inline, &mut args)?)?),
+            "--braille" if subcommand == "run" || subcommand == "tui" => options.braille = true,
+            "--terminal" if subcommand == "run" => options.terminal = true,
+            "--rom-db" if subcommand != "disasm" && subcommand != "cfg" => options.rom_db = Some(value(name, inline, &mut args)?),
+
+            "--keymap" if takes_options || subcommand == "tui" =>
+            {
+                let text = value(name, inline, &mut args)?;
+                options.keymap = Some(Keymap::parse(&text).ok_or_else(|| format!("unknown keymap '{}', \
+                    use one of {} or 16 keys in keypad order", text, Keymap::PRESETS.join(", ")))?);
+            },
+            "--record-input" if takes_options => options.record_input = Some(value(name, inline, &mut args)?),
+            "--replay" if takes_options => options.replay = Some(value(name, inline, &mut args)?),
+
+            "--mute" if takes_options => options.mute = true,
+            "--volume" if takes_options =>
+            {
+                options.volume = match number::<u32>(name, &value(name, inline, &mut args)?)?
+                {
+                    n if n <= 100 => n as u8,
+                    n => return Err(format!("--volume must be between 0 and 100, got {}", n)),
+                };
+            },
+
+            "--headless" if subcommand == "run" => options.headless = Some(number(name, &value(name, inline, &mut args)?)?),
+            "--frames" if subcommand == "bench" => options.headless = Some(number(name, &value(name, inline, &mut args)?)?),
+            "--record" if takes_options => options.record = Some(value(name, inline, &mut args)?),
+            "--screenshot" if takes_options => options.screenshot = Some(value(name, inline, &mut args)?),
+            "--video-out" if takes_options => options.video_out = Some(value(name, inline, &mut args)?),
+            "--video-scale" if takes_options => options.video_scale = positive(name, &value(name, inline, &mut args)?)?,
+            "--audio-out" if takes_options => options.audio_out = Some(value(name, inline, &mut args)?),
+            "--trace" if subcommand == "run" => options.trace = Some(value(name, inline, &mut args)?),
+            "--trace-range" if subcommand == "run" => options.trace_range = address_range(name, &value(name, inline, &mut args)?)?,
+            "--trace-paused" if subcommand == "run" => options.trace_paused = true,
+            "--profile" if subcommand == "run" => options.profile = Some(value(name, inline, &mut args)?),
+            "--profile-folded" if subcommand == "run" => options.profile_folded = Some(value(name, inline, &mut args)?),
+            "--coverage" if subcommand == "run" => options.coverage = Some(value(name, inline, &mut args)?),
+            "--coverage-report" if subcommand == "run" => options.coverage_report = Some(value(name, inline, &mut args)?),
+
+            _ => return Err(format!("unknown option '{}' for {}", name, subcommand)),
+        }
+    }
+
+    // a replay can't be recorded over
+    if options.record_input.is_some() && options.replay.is_some()
+    {
+        return Err("--record-input and --replay can't be used together".to_string());
+    }
+
+    if options.trace.is_none() && (options.trace_paused || options.trace_range != (0..=0xFFF))
+    {
+        return Err("--trace-range and --trace-paused need --trace".to_string());
+    }
+    if options.trace.as_deref() == Some("-") && options.video_out.as_deref() == Some("-")
+    {
+        return Err("--trace and --video-out can't both go to stdout".to_string());
+    }
+
+    // there is nothing to watch for without a window
+    if options.watch && options.headless.is_some()
+    {
+        return Err("--watch needs a window, it can't be used with --headless".to_string());
+    }
+    if options.debug && options.headless.is_some()
+    {
+        return Err("--debug needs a window, it can't be used with --headless".to_string());
+    }
+    if options.terminal && (options.debug || options.headless.is_some())
+    {
+        return Err("--terminal can't be used with --debug or --headless".to_string());
+    }
+
+    // without sdl there is no window, so whatever isn't headless plays in the terminal
+    if !cfg!(feature = "sdl")
+    {
+        if options.debug
+        {
+            return Err("--debug needs a build with sdl".to_string());
+        }
+        options.terminal = options.headless.is_none();
+    }
+
+    // the terminal frontend draws to stdout, nothing else can go there
+    if options.terminal && subcommand == "run"
+    {
+        let captures = [("--video-out", &options.video_out), ("--trace", &options.trace), ("--profile", &options.profile),
+            ("--profile-folded", &options.profile_folded), ("--coverage-report", &options.coverage_report)];
+        if let Some((name, _)) = captures.iter().find(|(_, path)| path.as_deref() == Some("-"))
+        {
+            return Err(format!("{} can't go to stdout while playing in the terminal, use --headless or a file", name));
+        }
+    }
+
+    // without a rom, run opens the launcher on the current directory
+    let rom_file = match rom_file
+    {
+        Some(rom_file) => rom_file,
+        None if subcommand == "run" => ".".to_string(),
+        None => return Err("no rom file given".to_string()),
+    };
+    Ok(match subcommand.as_str()
+    {
+        "disasm" => Command::Disasm(RunOptions { rom_file, ..options }),
+        "info" => Command::Info(RunOptions { rom_file, ..options }),
+        "lint" => Command::Lint(RunOptions { rom_file, ..options }),
+        "cfg" => Command::Cfg(RunOptions { rom_file, ..options }),
+        "bench" => Command::Bench(RunOptions { rom_file, ..options }),
+        "tui" => Command::Tui(RunOptions { rom_file, ..options }),
+        _ => Command::Run(RunOptions { rom_file, ..options }),
+    })
+}
@@ -0,0 +1,254 @@
+// Code coverage for --coverage: which addresses were run as code, which were
+// read as data (by DXYN and FX65) and which were written (by FX55 and FX33),
+// and how often each instruction ran. At the end of the run it goes out as
+// JSON, a flag string and a hit count per address touched, and with
+// --coverage-report as the rom's disassembly annotated with the same, as text
+// or, for a .html file, as a page with the untouched parts greyed out.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+
+use serde_json::json;
+
+use crate::cpu::VM;
+use crate::disasm;
+
+const MEMORY_SIZE: usize = 4096;
+
+const CODE: u8 = 1;
+const READ: u8 = 2;
+const WRITTEN: u8 = 4;
+
+pub struct Coverage
+{
+    flags: Vec<u8>,
+    hits: Vec<u64>, // instructions run starting at each address
+}
+
+// "xrw" with a dash for each flag that isn't set
+fn flag_string(flags: u8) -> String
+{
+    [(CODE, 'x'), (READ, 'r'), (WRITTEN, 'w')].iter()
+        .map(|&(flag, letter)| if flags & flag != 0 { letter } else { '-' })
+        .collect()
+}
+
+fn html_escape(text: &str) -> String
+{
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+const HTML_HEAD: &str = "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{title}</title>
+<style>
+body { background: #111; color: #ddd; font-family: monospace; }
+.code { color: #8f8; }
+.data { color: #8cf; }
+.written { color: #fc8; }
+.untouched { color: #555; }
+</style>
+</head>
+<body>
+<pre>
+";
+
+impl Coverage
+{
+    pub fn new() -> Coverage
+    {
+        Coverage
+        {
+            flags: vec![0; MEMORY_SIZE],
+            hits: vec![0; MEMORY_SIZE],
+        }
+    }
+
+    fn mark(& mut self, addresses: Range<usize>, flag: u8)
+    {
+        for address in addresses.filter(|&a| a < MEMORY_SIZE)
+        {
+            self.flags[address] |= flag;
+        }
+    }
+
+    // called before every instruction, so I and the registers are the ones
+    // it will use
+    pub fn record(& mut self, vm: &VM)
+    {
+        let registers = vm.registers();
+        let memory = vm.memory();
+        let pc = registers.pc as usize;
+        if pc + 1 >= memory.len()
+        {
+            return;
+        }
+        let opcode = (memory[pc] as u16) << 8 | memory[pc + 1] as u16;
+        self.hits[pc] += 1;
+        self.mark(pc..pc + 2, CODE);
+
+        let i = registers.i as usize;
+        let x = ((opcode & 0x0F00) >> 8) as usize;
+        match disasm::pattern(opcode)
+        {
+            "DXYN" => self.mark(i..i + (opcode & 0x000F) as usize, READ),
+            "FX65" => self.mark(i..i + x + 1, READ),
+            "FX55" => self.mark(i..i + x + 1, WRITTEN),
+            "FX33" => self.mark(i..i + 3, WRITTEN),
+            _ => {},
+        }
+    }
+
+    pub fn save_json(&self, path: &str, rom_name: &str, load_address: u16, rom_size: usize) -> io::Result<()>
+    {
+        let addresses: Vec<_> = (0..MEMORY_SIZE).filter(|&a| self.flags[a] != 0).map(|a| json!(
+        {
+            "address": a,
+            "flags": flag_string(self.flags[a]),
+            "hits": self.hits[a],
+        })).collect();
+        let report = json!(
+        {
+            "rom": rom_name,
+            "load_address": load_address,
+            "size": rom_size,
+            "addresses": addresses,
+        });
+
+        let mut out = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut out, &report)?;
+        writeln!(out)?;
+        out.flush()
+    }
+
+    // the listing, a line per instruction or data word. Code is lined up on
+    // the instructions that ran, so code at odd addresses still disassembles
+    // right, and anything else on words from the start of the rom.
+    fn listing(&self, memory: &[u8], rom: Range<usize>) -> Vec<(Range<usize>, String)>
+    {
+        let mut lines = Vec::new();
+        let mut address = rom.start;
+        while address < rom.end
+        {
+            let size = if address + 1 == rom.end || (self.hits[address] == 0 && self.hits[address + 1] != 0) { 1 } else { 2 };
+            let flags = self.flags[address..address + size].iter().fold(0, |all, &f| all | f);
+            let hits = match self.hits[address]
+            {
+                0 => "-".to_string(),
+                n => n.to_string(),
+            };
+            let text = if size == 2
+            {
+                let opcode = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+                format!("{:>10} {}  {:03X}: {:04X}  {}", hits, flag_string(flags), address, opcode, disasm::disassemble(opcode))
+            }
+            else
+            {
+                format!("{:>10} {}  {:03X}: {:02X}    DB 0x{:02X}", hits, flag_string(flags), address, memory[address],
+                    memory[address])
+            };
+            lines.push((address..address + size, text));
+            address += size;
+        }
+        lines
+    }
+
+    // runs of addresses outside the rom with the same flags, like the font
+    // read by DXYN or scratch memory written by FX55
+    fn outside(&self, rom: &Range<usize>) -> Vec<String>
+    {
+        let mut runs = Vec::new();
+        let mut address = 0;
+        while address < MEMORY_SIZE
+        {
+            let flags = self.flags[address];
+            if flags == 0 || rom.contains(&address)
+            {
+                address += 1;
+                continue;
+            }
+            let start = address;
+            while address < MEMORY_SIZE && self.flags[address] == flags && !rom.contains(&address)
+            {
+                address += 1;
+            }
+            runs.push(format!("{:03X}-{:03X} {}", start, address - 1, flag_string(flags)));
+        }
+        runs
+    }
+
+    fn summary(&self, rom: &Range<usize>) -> String
+    {
+        let count = |flag: u8| rom.clone().filter(|&a| self.flags[a] & flag != 0).count();
+        let untouched = rom.clone().filter(|&a| self.flags[a] == 0).count();
+        let percent = |n: usize| n as f64 * 100.0 / rom.len().max(1) as f64;
+        format!("{} bytes at {:03X}-{:03X}: {} run as code ({:.1}%), {} read as data ({:.1}%), {} written, {} untouched \
+            ({:.1}%)", rom.len(), rom.start, rom.end.saturating_sub(1), count(CODE), percent(count(CODE)), count(READ),
+            percent(count(READ)), count(WRITTEN), untouched, percent(untouched))
+    }
+
+    // the annotated disassembly of the rom at `rom`, as text or, for .html, a
+    // page; "-" is stdout
+    pub fn save_report(&self, path: &str, vm: &VM, rom_name: &str, rom: Range<usize>) -> io::Result<()>
+    {
+        let rom = rom.start.min(MEMORY_SIZE)..rom.end.min(MEMORY_SIZE);
+        let mut out: Box<dyn Write> = if path == "-"
+        {
+            Box::new(io::stdout())
+        }
+        else
+        {
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+        let lines = self.listing(vm.memory(), rom.clone());
+        let outside = self.outside(&rom);
+
+        if !path.to_lowercase().ends_with(".html")
+        {
+            writeln!(out, "{}\n{}\n", rom_name, self.summary(&rom))?;
+            writeln!(out, "{:>10} xrw  address, code or data", "hits")?;
+            for (_, text) in lines
+            {
+                writeln!(out, "{}", text)?;
+            }
+            if !outside.is_empty()
+            {
+                writeln!(out, "\noutside the rom:\n{}", outside.join("\n"))?;
+            }
+            return out.flush();
+        }
+
+        write!(out, "{}", HTML_HEAD.replace("{title}", &html_escape(rom_name)))?;
+        writeln!(out, "{}\n{}\n", html_escape(rom_name), self.summary(&rom))?;
+        for (bytes, text) in lines
+        {
+            let flags = self.flags[bytes].iter().fold(0, |all, &f| all | f);
+            let class = if flags & CODE != 0
+            {
+                "code"
+            }
+            else if flags & WRITTEN != 0
+            {
+                "written"
+            }
+            else if flags & READ != 0
+            {
+                "data"
+            }
+            else
+            {
+                "untouched"
+            };
+            writeln!(out, "<span class=\"{}\">{}</span>", class, html_escape(&text))?;
+        }
+        if !outside.is_empty()
+        {
+            writeln!(out, "\noutside the rom:\n{}", outside.join("\n"))?;
+        }
+        writeln!(out, "</pre>\n</body>\n</html>")?;
+        out.flush()
+    }
+}
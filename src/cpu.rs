@@ -25,10 +25,14 @@
 // Copyright (C) 2019 Daniel Pistelli / ntcore.com
 ///////////////////////////////////////////////////////////////////////////////
 
-use std::fs::File;
-use std::io::prelude::*;
-use rand;
+use std::fmt;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 
+use crate::loader::{LoadError, Rom};
+
+// where programs are loaded and start, unless told otherwise
+pub const PROGRAM_START: u16 = 0x200;
 
 const FONTSET: [u8; 80] =
 [
@@ -50,6 +54,115 @@ const FONTSET: [u8; 80] =
     0xF0, 0x80, 0xF0, 0x80, 0x80  // F
 ];
 
+// Behaviors that differ between CHIP-8 interpreters. The defaults are what
+// this VM has always done.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Quirks
+{
+    pub shift_vy: bool,               // 8XY6/8XYE shift VY into VX instead of shifting VX
+    pub load_store_increment_i: bool, // FX55/FX65 leave ir pointing past the last register
+    pub jump_vx: bool,                // BXNN jumps to XNN + VX instead of NNN + V0
+    pub vf_reset: bool,               // 8XY1/8XY2/8XY3 clear VF
+}
+
+impl Default for Quirks
+{
+    fn default() -> Quirks
+    {
+        Quirks
+        {
+            shift_vy: false,
+            load_store_increment_i: true,
+            jump_vx: false,
+            vf_reset: false,
+        }
+    }
+}
+
+// written as "shift_vy=0 load_store_increment_i=1 ...", see Quirks::parse
+impl fmt::Display for Quirks
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "shift_vy={} load_store_increment_i={} jump_vx={} vf_reset={}", self.shift_vy as u8,
+            self.load_store_increment_i as u8, self.jump_vx as u8, self.vf_reset as u8)
+    }
+}
+
+impl Quirks
+{
+    pub const PRESETS: [&'static str; 4] = ["rusty-8", "chip8", "schip", "xochip"];
+
+    // quirk sets of well known interpreters
+    pub fn preset(name: &str) -> Option<Quirks>
+    {
+        match name
+        {
+            "rusty-8" | "default" => Some(Quirks::default()),
+
+            // the original COSMAC VIP interpreter
+            "chip8" | "vip" => Some(Quirks { shift_vy: true, load_store_increment_i: true, jump_vx: false, vf_reset: true }),
+
+            // SUPER-CHIP 1.1 on the HP48
+            "schip" => Some(Quirks { shift_vy: false, load_store_increment_i: false, jump_vx: true, vf_reset: false }),
+
+            "xochip" => Some(Quirks { shift_vy: true, load_store_increment_i: true, jump_vx: false, vf_reset: false }),
+
+            _ => None,
+        }
+    }
+
+    // reads the Display format back; quirks that are not mentioned keep their default
+    pub fn parse(text: &str) -> Option<Quirks>
+    {
+        let mut quirks = Quirks::default();
+        for setting in text.split_whitespace()
+        {
+            let mut parts = setting.splitn(2, '=');
+            let name = parts.next()?;
+            let value = match parts.next()?
+            {
+                "0" => false,
+                "1" => true,
+                _ => return None,
+            };
+            match name
+            {
+                "shift_vy" => quirks.shift_vy = value,
+                "load_store_increment_i" => quirks.load_store_increment_i = value,
+                "jump_vx" => quirks.jump_vx = value,
+                "vf_reset" => quirks.vf_reset = value,
+                _ => return None,
+            }
+        }
+        Some(quirks)
+    }
+}
+
+// Something the program did that the VM can't carry on from
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Fault
+{
+    UnknownOpcode { pc: u16, opcode: u16 },
+    StackOverflow { pc: u16 },
+    StackUnderflow { pc: u16 },
+    MemoryOutOfBounds { pc: u16, address: u16 },
+}
+
+impl fmt::Display for Fault
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            Fault::UnknownOpcode { pc, opcode } => write!(f, "unknown opcode 0x{:04X} at 0x{:03X}", opcode, pc),
+            Fault::StackOverflow { pc } => write!(f, "stack overflow at 0x{:03X}", pc),
+            Fault::StackUnderflow { pc } => write!(f, "return with an empty stack at 0x{:03X}", pc),
+            Fault::MemoryOutOfBounds { pc, address } => write!(f, "memory access at 0x{:X} out of bounds at 0x{:03X}", address, pc),
+        }
+    }
+}
+
 pub struct VM
 {
     pc: u16,
@@ -69,6 +182,21 @@ pub struct VM
 
     pub draw_flag: bool,
     pub beep_flag: bool,
+
+    pub quirks: Quirks,
+    rng: StdRng,
+}
+
+// a copy of the registers for the debuggers to look at
+pub struct Registers
+{
+    pub pc: u16,
+    pub i: u16,
+    pub sp: u16,
+    pub v: [u8; 16],
+    pub stack: [u16; 16],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
 }
 
 impl VM
@@ -77,7 +205,7 @@ impl VM
     {
         let mut vm = VM
         {
-            pc: 0x200, // Program counter starts at 0x200
+            pc: PROGRAM_START, // Program counter starts at 0x200
             opcode: 0, // Reset current opcode
             ir: 0, // Reset index register
             sp: 0, // Reset stack pointer
@@ -97,21 +225,66 @@ impl VM
 
             draw_flag: true,
             beep_flag: false,
+
+            quirks: Quirks::default(),
+            rng: StdRng::seed_from_u64(rand::random()),
         };
 
         // Load fontset
-        for i in 0..80
+        vm.memory[..80].copy_from_slice(&FONTSET);
+
+        vm
+    }
+
+    // puts the VM back the way it is at power on: registers, stack, timers,
+    // screen and keys are cleared and execution restarts at `load_address`.
+    // Memory is left alone, a hard reset clears it with clear_memory as well.
+    pub fn reset(& mut self, load_address: u16)
+    {
+        self.pc = load_address;
+        self.opcode = 0;
+        self.ir = 0;
+        self.sp = 0;
+        self.v = [0; 16];
+        self.stack = [0; 16];
+        self.gfx = [0; 2048];
+        self.key = [0; 16];
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.draw_flag = true;
+        self.beep_flag = false;
+    }
+
+    // zeroes memory, except for the fontset
+    pub fn clear_memory(& mut self)
+    {
+        self.memory = [0; 4096];
+        self.memory[..80].copy_from_slice(&FONTSET);
+    }
+
+    // CXNN draws from this generator, so a fixed seed makes a run repeatable
+    pub fn seed_rng(& mut self, seed: u64)
+    {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    // returns the address as a memory index, or a fault if it's past the end of memory
+    fn address(&self, address: u16) -> Result<usize, Fault>
+    {
+        if (address as usize) < self.memory.len()
         {
-            vm.memory[i] = FONTSET[i];
+            Ok(address as usize)
+        }
+        else
+        {
+            Err(Fault::MemoryOutOfBounds { pc: self.pc, address })
         }
-
-        return vm;
     }
 
-    pub fn emulate_cycle(& mut self)
+    pub fn emulate_cycle(& mut self) -> Result<(), Fault>
     {
         // fetch opcode
-        self.opcode = (self.memory[self.pc as usize] as u16) << 8 | (self.memory[(self.pc + 1) as usize] as u16);
+        self.opcode = (self.memory[self.address(self.pc)?] as u16) << 8 | (self.memory[self.address(self.pc + 1)?] as u16);
 
         // println!("opcode: {:02X}{:02X}", (self.opcode >> 8) as u8, self.opcode as u8);
 
@@ -120,9 +293,9 @@ impl VM
         {
             0x0000 =>
             {
-                match self.opcode & 0x000F
+                match self.opcode
                 {
-                    0x0000 => // 0x00E0: clears the screen
+                    0x00E0 => // 0x00E0: clears the screen
                     {
                         for i in 0..2048
                         {
@@ -131,15 +304,19 @@ impl VM
                         self.draw_flag = true;
                         self.pc += 2;
                     },
-                    0x000E => // 0x00EE: returns from subroutine
+                    0x00EE => // 0x00EE: returns from subroutine
                     {
+                        if self.sp == 0
+                        {
+                            return Err(Fault::StackUnderflow { pc: self.pc });
+                        }
                         self.sp -= 1;                           // 16 levels of stack, decrease stack pointer to prevent overwrite
                         self.pc = self.stack[self.sp as usize]; // put the stored return address from the stack back into the program counter
                         self.pc += 2                            // don't forget to increase the program counter!
                     }
                     _ =>
                     {
-                        panic!("unknown opcode [0x0000]: 0x{:X}.", self.opcode);
+                        return Err(Fault::UnknownOpcode { pc: self.pc, opcode: self.opcode });
                     },
                 }
             },
@@ -151,6 +328,10 @@ impl VM
 
             0x2000 => // 0x2NNN: calls subroutine at NNN
             {
+                if self.sp as usize == self.stack.len()
+                {
+                    return Err(Fault::StackOverflow { pc: self.pc });
+                }
                 self.stack[self.sp as usize] = self.pc; // store your current address in the stack
                 self.sp += 1;                           // increment stack pointer
                 self.pc = self.opcode & 0x0FFF;         // set the program counter to the address at NNN
@@ -218,16 +399,28 @@ impl VM
                     0x0001 => // 0x8XY1 sets VX to "VX OR VY"
                     {
                         self.v[((self.opcode & 0x0F00) >> 8) as usize]  |= self.v[((self.opcode & 0x00F0) >> 4) as usize];
+                        if self.quirks.vf_reset
+                        {
+                            self.v[0xF] = 0;
+                        }
                         self.pc += 2;
                     },
                     0x0002 => // 0x8XY2: sets VX to "VX AND VY"
                     {
                         self.v[((self.opcode & 0x0F00) >> 8) as usize]  &= self.v[((self.opcode & 0x00F0) >> 4) as usize];
+                        if self.quirks.vf_reset
+                        {
+                            self.v[0xF] = 0;
+                        }
                         self.pc += 2;
                     },
                     0x0003 => // 0x8XY3: sets VX to "VX XOR VY"
                     {
                         self.v[((self.opcode & 0x0F00) >> 8) as usize]  ^= self.v[((self.opcode & 0x00F0) >> 4) as usize];
+                        if self.quirks.vf_reset
+                        {
+                            self.v[0xF] = 0;
+                        }
                         self.pc += 2;
                     },
                     0x0004 => // 0x8XY4: adds VY to VX. VF is set to 1 when there's a carry, and to 0 when there isn't
@@ -260,8 +453,10 @@ impl VM
                     },
                     0x0006 => // 0x8XY6: shifts VX right by one  VF is set to the value of the least significant bit of VX before the shift
                     {
-                        self.v[0xF] = self.v[((self.opcode & 0x0F00) >> 8) as usize] & 0x1;
-                        self.v[((self.opcode & 0x0F00) >> 8) as usize] >>= 1;
+                        let pos: usize = ((self.opcode & 0x0F00) >> 8) as usize;
+                        let value = if self.quirks.shift_vy { self.v[((self.opcode & 0x00F0) >> 4) as usize] } else { self.v[pos] };
+                        self.v[0xF] = value & 0x1;
+                        self.v[pos] = value >> 1;
                         self.pc += 2;
                     },
 
@@ -282,14 +477,16 @@ impl VM
 
                     0x000E => // 0x8XYE: shifts VX left by one. VF is set to the value of the most significant bit of VX before the shift
                     {
-                        self.v[0xF] = self.v[((self.opcode & 0x0F00) >> 8) as usize] >> 7;
-                        self.v[((self.opcode & 0x0F00) >> 8) as usize] <<= 1;
+                        let pos: usize = ((self.opcode & 0x0F00) >> 8) as usize;
+                        let value = if self.quirks.shift_vy { self.v[((self.opcode & 0x00F0) >> 4) as usize] } else { self.v[pos] };
+                        self.v[0xF] = value >> 7;
+                        self.v[pos] = value << 1;
                         self.pc += 2;
                     },
 
                     _ =>
                     {
-                        panic!("unknown opcode [0x8000]: 0x{:X}.", self.opcode);
+                        return Err(Fault::UnknownOpcode { pc: self.pc, opcode: self.opcode });
                     },
                 }
             },
@@ -312,14 +509,15 @@ impl VM
                 self.pc += 2;
             },
 
-            0xB000 =>
+            0xB000 => // BNNN: jumps to the address NNN plus V0 (or XNN plus VX)
             {
-                self.pc = (self.opcode & 0x0FFF).wrapping_add(self.v[0] as u16);
+                let offset = if self.quirks.jump_vx { self.v[((self.opcode & 0x0F00) >> 8) as usize] } else { self.v[0] };
+                self.pc = (self.opcode & 0x0FFF).wrapping_add(offset as u16);
             },
 
             0xC000 => // CXNN: sets VX to a random number and NN
             {
-                self.v[((self.opcode & 0x0F00) >> 8) as usize] = rand::random::<u8>() & (self.opcode as u8);
+                self.v[((self.opcode & 0x0F00) >> 8) as usize] = self.rng.gen::<u8>() & (self.opcode as u8);
                 self.pc += 2;
             },
 
@@ -341,7 +539,7 @@ impl VM
                 for yline in 0..height
                 {
                     // fetch the pixel value from the memory starting at location I (ir)
-                    let pixel = self.memory[(self.ir + yline) as usize] as u16;
+                    let pixel = self.memory[self.address(self.ir.wrapping_add(yline))?] as u16;
                     // loop over 8 bits in one row
                     for xline in 0..8
                     {
@@ -376,7 +574,7 @@ impl VM
                 {
                     0x009E => // EX9E: skips the next instruction if the key stored in VX is pressed
                     {
-                        if self.key[self.v[((self.opcode & 0x0F00) >> 8) as usize] as usize] != 0
+                        if self.key[(self.v[((self.opcode & 0x0F00) >> 8) as usize] & 0xF) as usize] != 0
                         {
                             self.pc += 4;
                         }
@@ -388,7 +586,7 @@ impl VM
 
                     0x00A1 => // EXA1: skips the next instructions if the key stored in VX isn't pressed
                     {
-                        if self.key[self.v[((self.opcode & 0x0F00) >> 8) as usize] as usize] == 0
+                        if self.key[(self.v[((self.opcode & 0x0F00) >> 8) as usize] & 0xF) as usize] == 0
                         {
                             self.pc += 4;
                         }
@@ -400,7 +598,7 @@ impl VM
 
                     _ =>
                     {
-                        panic!("unknown opcode [0xE000]: 0x{:X}.", self.opcode);
+                        return Err(Fault::UnknownOpcode { pc: self.pc, opcode: self.opcode });
                     },
                 }
             },
@@ -471,9 +669,11 @@ impl VM
 
                     0x0033 => // FX33: stores the binary-coded decimal representation of VX at the addresses ir, ir plus 1, and ir plus 2
                     {
-                        self.memory[self.ir as usize] = self.v[((self.opcode & 0x0F00) >> 8) as usize] / 100;
-                        self.memory[(self.ir + 1) as usize] = (self.v[((self.opcode & 0x0F00) >> 8) as usize] / 10) % 10;
-                        self.memory[(self.ir + 2) as usize] = (self.v[((self.opcode & 0x0F00) >> 8) as usize] % 100) % 10;
+                        let vx = self.v[((self.opcode & 0x0F00) >> 8) as usize];
+                        for (k, &digit) in [vx / 100, (vx / 10) % 10, vx % 10].iter().enumerate()
+                        {
+                            self.memory[self.address(self.ir.wrapping_add(k as u16))?] = digit;
+                        }
                         self.pc += 2;
                     },
 
@@ -482,11 +682,14 @@ impl VM
                         let j = (self.opcode & 0x0F00) >> 8;
                         for i in 0..j + 1
                         {
-                            self.memory[(self.ir + i) as usize] = self.v[i as usize];
+                            self.memory[self.address(self.ir.wrapping_add(i))?] = self.v[i as usize];
                         }
 
                         // on the original intepreter, when the operation is done, ir = ir + X + 1.
-                        self.ir = self.ir.wrapping_add(j + 1);
+                        if self.quirks.load_store_increment_i
+                        {
+                            self.ir = self.ir.wrapping_add(j + 1);
+                        }
                         self.pc += 2;
                     },
 
@@ -495,28 +698,37 @@ impl VM
                         let j = (self.opcode & 0x0F00) >> 8;
                         for i in 0..j + 1
                         {
-                            self.v[i as usize] = self.memory[(self.ir + i) as usize];
+                            self.v[i as usize] = self.memory[self.address(self.ir.wrapping_add(i))?];
                         }
 
                         // on the original interpreter, when the operation is done, ir = ir + X + 1
-                        self.ir = self.ir.wrapping_add(j + 1);
+                        if self.quirks.load_store_increment_i
+                        {
+                            self.ir = self.ir.wrapping_add(j + 1);
+                        }
                         self.pc += 2;
                     },
 
                     _ =>
                     {
-                        panic!("unknown opcode [0xF000]: 0x{:X}.", self.opcode);
+                        return Err(Fault::UnknownOpcode { pc: self.pc, opcode: self.opcode });
                     },
                 }
             },
 
             _ =>
             {
-                panic!("unknown opcode [0x0000]: 0x{:X}.", self.opcode);
+                return Err(Fault::UnknownOpcode { pc: self.pc, opcode: self.opcode });
             },
         }
 
-        // update timers
+        Ok(())
+    }
+
+    // the delay and sound timers count down at 60 Hz, independently of how
+    // many instructions are executed in a frame
+    pub fn tick_timers(& mut self)
+    {
         if self.delay_timer > 0
         {
             self.delay_timer -= 1;
@@ -532,55 +744,104 @@ impl VM
         }
     }
 
-    pub fn debug_render(& self)
+    pub fn sound_playing(&self) -> bool
+    {
+        self.sound_timer > 0
+    }
+
+    pub fn registers(&self) -> Registers
     {
-        // draw
-        for y in 0..32
+        Registers
         {
-            for x in 0..64
-            {
-                if self.gfx[((y * 64) + x) as usize] == 0
-                {
-                    print!("0");
-                }
-                else
-                {
-                    print!(" ");
-                }
-            }
-            println!("");
+            pc: self.pc,
+            i: self.ir,
+            sp: self.sp,
+            v: self.v,
+            stack: self.stack,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
         }
-        println!("");
     }
 
-    pub fn load_application(& mut self, filename: &str) -> bool
+    pub fn memory(&self) -> &[u8]
     {
-        // open the file
-        let mut file = File::open(filename).expect("file error");
+        &self.memory
+    }
 
-        // get the file size
-        let fsize = file.metadata().unwrap().len();
+    // reads a rom (a file, "-" or a zip archive, see Rom::open) and loads it,
+    // see load_bytes
+    pub fn load_application(& mut self, filename: &str, load_address: u16) -> Result<Rom, LoadError>
+    {
+        let rom = Rom::open(filename)?;
+        self.load_bytes(&rom.bytes, load_address)?;
+        Ok(rom)
+    }
 
-        // read the file to a buffer
-        let mut buffer = vec![];
-        file.read_to_end(&mut buffer).expect("couldn't read file");
-        drop(file);
+    // copies a rom into memory at `load_address` (0x200 for almost everything,
+    // 0x600 for the ETI-660) and starts executing it from there
+    pub fn load_bytes(& mut self, rom: &[u8], load_address: u16) -> Result<(), LoadError>
+    {
+        self.replace_program(rom, load_address)?;
+        self.pc = load_address;
+        Ok(())
+    }
 
-        // copy the buffer to the chip8 memory
-        if (4096 - 512) > fsize
+    // copies a rom over the program area and leaves everything else, registers
+    // and pc included, as it was
+    pub fn replace_program(& mut self, rom: &[u8], load_address: u16) -> Result<(), LoadError>
+    {
+        let start = load_address as usize;
+        if start < FONTSET.len() || start >= self.memory.len()
         {
-            for i in 0..fsize
-            {
-                self.memory[(i + 512) as usize] = buffer[i as usize];
-                // println!("{}", buffer[i as usize]);
-            }
+            return Err(LoadError::BadLoadAddress(load_address));
         }
-        else
+        if rom.is_empty()
+        {
+            return Err(LoadError::Empty);
+        }
+
+        let available = self.memory.len() - start;
+        if rom.len() > available
         {
-            panic!("ROM too big for memory");
+            return Err(LoadError::TooBig { size: rom.len(), available });
         }
 
-        return true;
+        // copy the buffer to the chip8 memory
+        self.memory[start..start + rom.len()].copy_from_slice(rom);
+        Ok(())
     }
 }
 
+#[cfg(test)]
+mod tests
+{
+    use super::{Fault, VM};
+
+    // a VM about to run `opcode` at 0x200, with V0 = 254 and I at `ir`
+    fn vm(opcode: u16, ir: u16) -> VM
+    {
+        let mut vm = VM::new();
+        vm.load_bytes(&opcode.to_be_bytes(), 0x200).unwrap();
+        vm.v[0] = 254;
+        vm.ir = ir;
+        vm
+    }
+
+    #[test]
+    fn bcd()
+    {
+        let mut vm = vm(0xF033, 0x300);
+        vm.emulate_cycle().unwrap();
+        assert_eq!(vm.memory[0x300..0x303], [2, 5, 4]);
+    }
+
+    #[test]
+    fn bcd_out_of_memory_faults()
+    {
+        for &ir in &[0xFFFF, 0xFFFE, 0xFFF]
+        {
+            let mut vm = vm(0xF033, ir);
+            assert!(matches!(vm.emulate_cycle(), Err(Fault::MemoryOutOfBounds { pc: 0x200, .. })), "I = {:X}", ir);
+        }
+    }
+}
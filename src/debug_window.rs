@@ -0,0 +1,226 @@
+// A second window with the state of the VM: registers and timers, the stack,
+// the keypad, a disassembly following pc and a memory dump with pc and I
+// marked. F9 (or --debug) opens it; it's redrawn every frame, so it works
+// with pause and frame advance. The mouse wheel or page up and down over it
+// scroll the memory dump.
+
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use crate::cpu::VM;
+use crate::disasm;
+use crate::font::{self, Bitmap};
+use crate::palette::Palette;
+use crate::scale::ScaleMode;
+use crate::screen::{self, Screen};
+
+const COLUMNS: usize = 88;
+const ROWS: usize = 40;
+const WIDTH: usize = COLUMNS * font::CELL_WIDTH;
+const HEIGHT: usize = ROWS * font::CELL_HEIGHT;
+const SCALE: u32 = 2;
+
+const RIGHT: usize = 30;            // column where the disassembly and memory panes start
+const DISASM_ROWS: usize = 17;
+const MEMORY_ROW: usize = DISASM_ROWS + 3;
+const MEMORY_ROWS: usize = ROWS - MEMORY_ROW - 1;
+const BYTES_PER_ROW: usize = 16;
+const MEMORY_SIZE: usize = 4096;
+const LAST_MEMORY_TOP: usize = MEMORY_SIZE / BYTES_PER_ROW - MEMORY_ROWS; // the dump can't scroll past the end
+
+// the keypad as it's laid out on the COSMAC VIP
+const KEYPAD: [[usize; 4]; 4] = [[0x1, 0x2, 0x3, 0xC], [0x4, 0x5, 0x6, 0xD], [0x7, 0x8, 0x9, 0xE], [0xA, 0x0, 0xB, 0xF]];
+
+pub struct DebugWindow
+{
+    canvas: Canvas<Window>,
+    bitmap: Bitmap,
+    memory_top: usize, // first row of the memory dump
+}
+
+// cell coordinates to pixels
+fn at(column: usize, row: usize) -> (usize, usize)
+{
+    (column * font::CELL_WIDTH, row * font::CELL_HEIGHT + 1)
+}
+
+impl DebugWindow
+{
+    pub fn open(screen: &Screen, load_address: u16) -> Result<DebugWindow, String>
+    {
+        let window = screen.sdl.video()?.window("rusty-8 debug", WIDTH as u32 * SCALE, HEIGHT as u32 * SCALE)
+            .resizable().build()
+            .map_err(|e| e.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+
+        Ok(DebugWindow
+        {
+            canvas,
+            bitmap: Bitmap::new(WIDTH, HEIGHT),
+            memory_top: (load_address as usize / BYTES_PER_ROW).min(LAST_MEMORY_TOP),
+        })
+    }
+
+    // events carry the id of the window they happened in
+    pub fn window_id(&self) -> u32
+    {
+        self.canvas.window().id()
+    }
+
+    // scrolls the memory dump by `rows`, negative is up
+    pub fn scroll(& mut self, rows: i32)
+    {
+        self.memory_top = (self.memory_top as i32 + rows).clamp(0, LAST_MEMORY_TOP as i32) as usize;
+    }
+
+    pub fn page(& mut self, pages: i32)
+    {
+        self.scroll(pages * MEMORY_ROWS as i32);
+    }
+
+    fn text(& mut self, column: usize, row: usize, text: &str, color: [u8; 3])
+    {
+        let (x, y) = at(column, row);
+        self.bitmap.draw_text(x, y, text, color);
+    }
+
+    // text drawn in reverse, for whatever pc is on
+    fn highlight(& mut self, column: usize, row: usize, text: &str, palette: &Palette)
+    {
+        let (x, y) = at(column, row);
+        self.bitmap.fill_rect(x, y - 1, font::text_width(text), font::CELL_HEIGHT, palette.foreground);
+        self.bitmap.draw_text(x, y, text, palette.background);
+    }
+
+    fn draw_registers(& mut self, vm: &VM, frame: u64, palette: &Palette)
+    {
+        let fg = palette.foreground;
+        let registers = vm.registers();
+
+        self.text(0, 0, "registers", fg);
+        for row in 0..8
+        {
+            let text = format!("V{:X} {:02X}  V{:X} {:02X}", row, registers.v[row], row + 8, registers.v[row + 8]);
+            self.text(1, row + 1, &text, fg);
+        }
+        self.text(1, 9, &format!("PC {:03X}  I {:03X}", registers.pc, registers.i), fg);
+        self.text(1, 10, &format!("DT {:02X}   ST {:02X}", registers.delay_timer, registers.sound_timer), fg);
+        self.text(1, 11, &format!("frame {}", frame), fg);
+
+        // each level holds the address of its call, the return goes past it
+        self.text(0, 13, &format!("stack  SP {}", registers.sp), fg);
+        for level in 0..registers.stack.len()
+        {
+            let text = if level < registers.sp as usize
+            {
+                format!("{:2} {:03X} ret {:03X}", level, registers.stack[level], registers.stack[level] + 2)
+            }
+            else
+            {
+                format!("{:2} ---", level)
+            };
+            self.text(1, 14 + level, &text, fg);
+        }
+
+        self.text(0, 31, "keypad", fg);
+        for (row, keys) in KEYPAD.iter().enumerate()
+        {
+            for (i, &key) in keys.iter().enumerate()
+            {
+                let text = format!("{:X}", key);
+                if vm.key[key] != 0
+                {
+                    self.highlight(1 + i * 2, 32 + row, &text, palette);
+                }
+                else
+                {
+                    self.text(1 + i * 2, 32 + row, &text, fg);
+                }
+            }
+        }
+    }
+
+    // a few instructions before pc and the rest after it. Instructions are
+    // lined up from pc, which is the only address known to hold one.
+    fn draw_disassembly(& mut self, vm: &VM, palette: &Palette)
+    {
+        let fg = palette.foreground;
+        let pc = vm.registers().pc as usize;
+        let memory = vm.memory();
+
+        self.text(RIGHT, 0, "disassembly", fg);
+        let first = pc.saturating_sub(8);
+        for row in 0..DISASM_ROWS
+        {
+            let address = first + row * 2;
+            if address + 1 >= memory.len()
+            {
+                break;
+            }
+            let opcode = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+            let text = format!("{:03X}: {:04X}  {}", address, opcode, disasm::disassemble(opcode));
+            if address == pc
+            {
+                self.highlight(RIGHT + 1, row + 1, &text, palette);
+            }
+            else
+            {
+                self.text(RIGHT + 1, row + 1, &text, fg);
+            }
+        }
+    }
+
+    // pc's two bytes are drawn in reverse and the byte at I underlined
+    fn draw_memory(& mut self, vm: &VM, palette: &Palette)
+    {
+        let fg = palette.foreground;
+        let registers = vm.registers();
+        let (pc, i) = (registers.pc as usize, registers.i as usize);
+
+        self.text(RIGHT, MEMORY_ROW - 1, "memory", fg);
+        for row in 0..MEMORY_ROWS
+        {
+            let start = (self.memory_top + row) * BYTES_PER_ROW;
+            self.text(RIGHT + 1, MEMORY_ROW + row, &format!("{:03X}:", start), fg);
+            for column in 0..BYTES_PER_ROW
+            {
+                let address = start + column;
+                let text = format!("{:02X}", vm.memory()[address]);
+                let cell = RIGHT + 6 + column * 3;
+                if address == pc || address == pc + 1
+                {
+                    self.highlight(cell, MEMORY_ROW + row, &text, palette);
+                }
+                else
+                {
+                    self.text(cell, MEMORY_ROW + row, &text, fg);
+                }
+                if address == i
+                {
+                    let (x, y) = at(cell, MEMORY_ROW + row);
+                    self.bitmap.fill_rect(x, y + font::GLYPH_HEIGHT, font::text_width(&text) - 1, 1, fg);
+                }
+            }
+        }
+    }
+
+    pub fn draw(& mut self, vm: &VM, frame: u64, palette: &Palette) -> Result<(), String>
+    {
+        self.bitmap.fill_rect(0, 0, WIDTH, HEIGHT, palette.background);
+        self.draw_registers(vm, frame, palette);
+        self.draw_disassembly(vm, palette);
+        self.draw_memory(vm, palette);
+
+        // the texture can't outlive its creator, which can't be kept next to
+        // the canvas it comes from, so it's made for every frame
+        let texture_creator = self.canvas.texture_creator();
+        let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, WIDTH as u32,
+            HEIGHT as u32).map_err(|e| e.to_string())?;
+        texture.update(None, &self.bitmap.pixels, WIDTH * 3).map_err(|e| e.to_string())?;
+        screen::draw_texture(&mut self.canvas, &texture, WIDTH as u32, HEIGHT as u32, ScaleMode::Integer,
+            palette.background)?;
+        self.canvas.present();
+        Ok(())
+    }
+}
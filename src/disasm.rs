@@ -0,0 +1,120 @@
+// Turns opcodes into mnemonics, using the syntax of Cowgod's CHIP-8
+// technical reference (LD, SE, DRW, ...), decoded the way the VM runs them.
+// Anything it faults on comes out as a DW data word.
+
+pub fn disassemble(opcode: u16) -> String
+{
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let n = opcode & 0x000F;
+    let nn = opcode & 0x00FF;
+    let nnn = opcode & 0x0FFF;
+
+    match opcode & 0xF000
+    {
+        0x0000 => match opcode
+        {
+            0x00E0 => "CLS".to_string(),
+            0x00EE => "RET".to_string(),
+            // machine code calls, which the VM doesn't run
+            _ => format!("DW 0x{:04X}", opcode),
+        },
+        0x1000 => format!("JP 0x{:03X}", nnn),
+        0x2000 => format!("CALL 0x{:03X}", nnn),
+        0x3000 => format!("SE V{:X}, 0x{:02X}", x, nn),
+        0x4000 => format!("SNE V{:X}, 0x{:02X}", x, nn),
+        // the low nibble is ignored, as the VM does
+        0x5000 => format!("SE V{:X}, V{:X}", x, y),
+        0x6000 => format!("LD V{:X}, 0x{:02X}", x, nn),
+        0x7000 => format!("ADD V{:X}, 0x{:02X}", x, nn),
+        0x8000 => match n
+        {
+            0x0 => format!("LD V{:X}, V{:X}", x, y),
+            0x1 => format!("OR V{:X}, V{:X}", x, y),
+            0x2 => format!("AND V{:X}, V{:X}", x, y),
+            0x3 => format!("XOR V{:X}, V{:X}", x, y),
+            0x4 => format!("ADD V{:X}, V{:X}", x, y),
+            0x5 => format!("SUB V{:X}, V{:X}", x, y),
+            0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xE => format!("SHL V{:X}, V{:X}", x, y),
+            _ => format!("DW 0x{:04X}", opcode),
+        },
+        0x9000 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA000 => format!("LD I, 0x{:03X}", nnn),
+        0xB000 => format!("JP V0, 0x{:03X}", nnn),
+        0xC000 => format!("RND V{:X}, 0x{:02X}", x, nn),
+        0xD000 => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE000 => match nn
+        {
+            0x9E => format!("SKP V{:X}", x),
+            0xA1 => format!("SKNP V{:X}", x),
+            _ => format!("DW 0x{:04X}", opcode),
+        },
+        0xF000 => match nn
+        {
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            _ => format!("DW 0x{:04X}", opcode),
+        },
+        _ => format!("DW 0x{:04X}", opcode),
+    }
+}
+
+// the instruction an opcode is an instance of, written the usual way with
+// its operands as letters (8XY4, DXYN, ...), or "data" if the VM faults on it
+pub fn pattern(opcode: u16) -> &'static str
+{
+    const ALU: [&str; 16] = ["8XY0", "8XY1", "8XY2", "8XY3", "8XY4", "8XY5", "8XY6", "8XY7",
+        "data", "data", "data", "data", "data", "data", "8XYE", "data"];
+
+    match opcode & 0xF000
+    {
+        0x0000 => match opcode
+        {
+            0x00E0 => "00E0",
+            0x00EE => "00EE",
+            _ => "data",
+        },
+        0x1000 => "1NNN",
+        0x2000 => "2NNN",
+        0x3000 => "3XNN",
+        0x4000 => "4XNN",
+        0x5000 => "5XY0",
+        0x6000 => "6XNN",
+        0x7000 => "7XNN",
+        0x8000 => ALU[(opcode & 0xF) as usize],
+        0x9000 => "9XY0",
+        0xA000 => "ANNN",
+        0xB000 => "BNNN",
+        0xC000 => "CXNN",
+        0xD000 => "DXYN",
+        0xE000 => match opcode & 0xFF
+        {
+            0x9E => "EX9E",
+            0xA1 => "EXA1",
+            _ => "data",
+        },
+        0xF000 => match opcode & 0xFF
+        {
+            0x07 => "FX07",
+            0x0A => "FX0A",
+            0x15 => "FX15",
+            0x18 => "FX18",
+            0x1E => "FX1E",
+            0x29 => "FX29",
+            0x33 => "FX33",
+            0x55 => "FX55",
+            0x65 => "FX65",
+            _ => "data",
+        },
+        _ => "data",
+    }
+}
@@ -0,0 +1,368 @@
+// The frontends and the loop that drives the VM through them. A frontend
+// shows the screen (Display), makes the beep (Audio) and reads the keypad and
+// the controls (Input); the runner here does the rest, the same for all of
+// them: frame timing, pause and the speed controls, resets, --watch,
+// screenshots and gif recording. The window (window.rs), the terminal
+// (terminal.rs), its debugger (tui.rs) and --headless (Headless below) are
+// the frontends.
+//
+// A frontend usually implements all three traits on one type, since its
+// input and its display tend to share a window.
+
+use std::path::Path;
+use std::time::Instant;
+
+use crate::cli::RunOptions;
+use crate::cpu::Fault;
+use crate::loader::{Rom, Watcher};
+use crate::{run_frame, run_instruction, screenshot, start_recording, stop_recording, Game};
+use crate::{FRAME_DURATION, SCREEN_HEIGHT, SCREEN_WIDTH, WATCH_INTERVAL};
+
+// slow motion runs a frame of the VM every fourth frame of the frontend
+const SLOW_MOTION: f64 = 0.25;
+
+// the controls a frontend can ask the runner for
+pub enum Action
+{
+    Quit,                        // the window was closed
+    Menu,                        // escape, back to the launcher if there is one
+    Reset { hard: bool },        // reload the rom, clearing memory when hard
+    Load(String),                // run another rom, e.g. one dropped onto the window
+    Pause,                       // stop or resume the VM
+    Advance,                     // run a single frame while paused
+    Step,                        // or a single instruction
+    FastForward(bool),           // while held
+    SlowMotion,                  // on or off
+    Screenshot { native: bool }, // at 64x32 rather than the window scale
+    Record,                      // start or stop a gif recording
+    Trace,                       // turn --trace on or off
+    Breakpoint(u16),             // set or clear one at an address
+}
+
+// how the runner ended
+pub enum Exit
+{
+    Quit,
+    Menu,
+}
+
+// the state of the runner, for the frontend to show
+pub struct Frame
+{
+    pub fault: Option<Fault>, // stops the VM until a reset
+    pub speed: f64,           // 1.0 unless fast forwarding or in slow motion
+    pub emulated: u32,        // frames of the VM run since the last draw
+}
+
+impl Frame
+{
+    // what's worth telling about the VM, a line each
+    pub fn status(&self, game: &Game) -> Vec<String>
+    {
+        let mut status = Vec::new();
+        match self.fault
+        {
+            Some(fault) => status.extend([format!("vm fault: {}", fault), "F5 resets".to_string()]),
+            None if game.session.paused => status.push(format!("paused at frame {}, F7 advances", game.session.frame)),
+            None => {},
+        }
+        if self.speed != 1.0
+        {
+            status.push(format!("speed {}x", self.speed));
+        }
+        status
+    }
+
+    fn result(&self) -> Result<(), Fault>
+    {
+        self.fault.map_or(Ok(()), Err)
+    }
+}
+
+pub trait Display
+{
+    // a rom was started, the window title may want to change
+    fn loaded(& mut self, _game: &Game) {}
+
+    // a short message, such as "reset"
+    fn notify(& mut self, text: &str);
+
+    // one about something that went wrong
+    fn error(& mut self, text: &str);
+
+    // called once a frame; the VM's draw_flag says whether its screen changed
+    fn draw(& mut self, game: &Game, frame: &Frame) -> Result<(), String>;
+}
+
+pub trait Audio
+{
+    // called once a frame unless --mute: `started` when the program set the
+    // sound timer since the last call, `playing` while it runs
+    fn sound(& mut self, started: bool, playing: bool);
+}
+
+pub trait Input
+{
+    // waits until `deadline` for input, when the next frame is due, and
+    // returns the controls used in the meantime
+    fn poll(& mut self, game: &Game, frame: &Frame, deadline: Instant) -> Result<Vec<Action>, String>;
+
+    // sets the keys held down, before the VM runs. A replayed movie sets
+    // them again inside run_frame.
+    fn keypad(& mut self, keys: &mut [u8; 16]);
+}
+
+fn save_screenshot(game: &Game, scale: u32) -> Result<String, String>
+{
+    let file_name = screenshot::file_name(&game.rom.name, "png");
+    screenshot::save_png(Path::new(&file_name), &game.vm.gfx, SCREEN_WIDTH as usize, SCREEN_HEIGHT as usize,
+        &game.palette, scale as usize)
+        .map(|()| format!("saved screenshot {}", file_name))
+        .map_err(|e| format!("failed to save screenshot {}: {}", file_name, e))
+}
+
+// runs the game until the frontend quits or goes back to the menu. A fault
+// stops the VM but leaves the frontend up showing it, a reset starts over;
+// it is returned at the end. Errors are the frontend's own.
+pub fn run<F>(game: &mut Game, options: &RunOptions, frontend: &mut F) -> Result<(Exit, Result<(), Fault>), String>
+    where F: Display + Audio + Input
+{
+    game.vm.draw_flag = true;
+    frontend.loaded(game);
+
+    let mut frame = Frame { fault: None, speed: 1.0, emulated: 0 };
+    let mut watcher = None;
+    if options.watch
+    {
+        watcher = Watcher::new(&game.rom.name);
+        if watcher.is_none()
+        {
+            eprintln!("standard input can't be watched for changes");
+        }
+    }
+    let mut polls: u64 = 0;
+    let mut next_frame = Instant::now();
+
+    // the speed controls change how many VM frames run per frontend frame, so
+    // instructions and timers speed up and slow down together
+    let mut fast_forward = false;
+    let mut slow_motion = false;
+    let mut advance = false;
+    let mut step = false;
+    let mut frames_due = 0.0;
+
+    loop
+    {
+        for action in frontend.poll(game, &frame, next_frame)?
+        {
+            match action
+            {
+                Action::Quit => return Ok((Exit::Quit, frame.result())),
+                Action::Menu => return Ok((Exit::Menu, frame.result())),
+
+                Action::Reset { hard } => match game.reload(options, hard)
+                {
+                    Ok(()) =>
+                    {
+                        frontend.notify(if hard { "hard reset" } else { "reset" });
+                        frame.fault = None;
+                    },
+                    Err(e) => frontend.error(&format!("failed to reload rom {}", e)),
+                },
+                Action::Load(file_name) =>
+                {
+                    match Rom::open(&file_name).and_then(|rom| game.restart(options, rom, true))
+                    {
+                        Ok(()) =>
+                        {
+                            frontend.loaded(game);
+                            frontend.notify(&format!("loaded {}", game.rom.name));
+                            frame.fault = None;
+                            if options.watch
+                            {
+                                watcher = Watcher::new(&game.rom.name);
+                            }
+                        },
+                        Err(e) => frontend.error(&format!("failed to load rom {}", e)),
+                    }
+                },
+
+                // carrying on from a breakpoint runs its instruction
+                Action::Pause =>
+                {
+                    game.session.paused = !game.session.paused;
+                    game.session.resumed = true;
+                },
+                Action::Advance =>
+                {
+                    advance = game.session.paused;
+                    game.session.resumed = advance;
+                },
+                // a movie goes by whole frames, an instruction on its own would
+                // put a replay out of step with the recording
+                Action::Step if game.session.movie.is_some() =>
+                {
+                    frontend.error("can't step an instruction while an input movie records or replays, F7 advances a frame");
+                },
+                Action::Step => step = game.session.paused,
+                Action::FastForward(on) => fast_forward = on,
+                Action::SlowMotion => slow_motion = !slow_motion,
+
+                Action::Screenshot { native } =>
+                {
+                    match save_screenshot(game, if native { 1 } else { options.display_modifier })
+                    {
+                        Ok(message) => frontend.notify(&message),
+                        Err(message) => frontend.error(&message),
+                    }
+                },
+                Action::Record => match game.session.recorder.take()
+                {
+                    Some(rec) => stop_recording(Some(rec)),
+                    None => game.session.recorder = start_recording(&screenshot::file_name(&game.rom.name, "gif"),
+                        options, &game.palette),
+                },
+                Action::Trace => match game.session.trace
+                {
+                    Some(ref mut trace) => frontend.notify(if trace.toggle() { "tracing on" } else { "tracing off" }),
+                    None => frontend.error("nothing to trace to, run with --trace FILE"),
+                },
+                Action::Breakpoint(address) =>
+                {
+                    if !game.session.breakpoints.remove(&address)
+                    {
+                        game.session.breakpoints.insert(address);
+                    }
+                },
+            }
+        }
+
+        // a load error leaves the old rom running, so a half saved file doesn't end the session
+        polls += 1;
+        if polls.is_multiple_of(WATCH_INTERVAL) && watcher.as_mut().is_some_and(Watcher::changed)
+        {
+            match game.hot_reload(options)
+            {
+                Ok(()) =>
+                {
+                    frontend.notify(&format!("reloaded {}", game.rom.name));
+                    frame.fault = None;
+                },
+                Err(e) => frontend.error(&format!("failed to reload rom {}", e)),
+            }
+        }
+
+        frame.speed = match (fast_forward, slow_motion)
+        {
+            (true, _) => options.fast_forward as f64,
+            (false, true) => SLOW_MOTION,
+            (false, false) => 1.0,
+        };
+        if game.session.paused
+        {
+            frames_due = if advance { 1.0 } else { 0.0 };
+            advance = false;
+        }
+        else
+        {
+            frames_due += frame.speed;
+        }
+
+        frontend.keypad(&mut game.vm.key);
+
+        // the instruction that completes a frame ends it, as run_frame would
+        if step && frame.fault.is_none()
+        {
+            frame.fault = run_instruction(&mut game.vm, &mut game.session).err();
+        }
+        step = false;
+
+        frame.emulated = 0;
+        while frames_due >= 1.0 && frame.fault.is_none()
+        {
+            frames_due -= 1.0;
+            let number = game.session.frame;
+            match run_frame(&mut game.vm, &mut game.session)
+            {
+                // a frame left unfinished stopped at a breakpoint
+                Ok(()) if game.session.frame == number =>
+                {
+                    frontend.notify(&format!("breakpoint at {:03X}", game.vm.registers().pc));
+                    frames_due = 0.0;
+                },
+                Ok(()) => frame.emulated += 1,
+                Err(e) => frame.fault = Some(e),
+            }
+        }
+
+        if !options.mute
+        {
+            frontend.sound(game.vm.beep_flag, game.vm.sound_playing());
+        }
+        game.vm.beep_flag = false;
+
+        frontend.draw(game, &frame)?;
+        game.vm.draw_flag = false;
+
+        // don't try to catch up after a stall
+        next_frame += FRAME_DURATION;
+        let now = Instant::now();
+        if next_frame < now
+        {
+            next_frame = now;
+        }
+    }
+}
+
+// --headless: nothing shown or heard, and no waiting between frames, until
+// the frames run out or the VM faults
+pub struct Headless
+{
+    frames: u64,
+}
+
+impl Headless
+{
+    pub fn new(frames: u64) -> Headless
+    {
+        Headless { frames }
+    }
+}
+
+impl Display for Headless
+{
+    fn notify(& mut self, text: &str)
+    {
+        eprintln!("{}", text);
+    }
+
+    fn error(& mut self, text: &str)
+    {
+        eprintln!("{}", text);
+    }
+
+    fn draw(& mut self, _game: &Game, _frame: &Frame) -> Result<(), String>
+    {
+        Ok(())
+    }
+}
+
+impl Audio for Headless
+{
+    fn sound(& mut self, _started: bool, _playing: bool) {}
+}
+
+impl Input for Headless
+{
+    fn poll(& mut self, game: &Game, frame: &Frame, _deadline: Instant) -> Result<Vec<Action>, String>
+    {
+        if frame.fault.is_some() || game.session.frame >= self.frames
+        {
+            return Ok(vec![Action::Quit]);
+        }
+        Ok(Vec::new())
+    }
+
+    // the keys are only ever pressed by a replayed movie
+    fn keypad(& mut self, _keys: &mut [u8; 16]) {}
+}
@@ -0,0 +1,74 @@
+// Maps keyboard characters onto the hex keypad. A keymap is written as the
+// 16 characters that sit where the keypad keys are, row by row:
+//
+//   1 2 3 C
+//   4 5 6 D
+//   7 8 9 E
+//   A 0 B F
+//
+// so "1234qwerasdfzxcv" puts the keypad on the left hand side of a qwerty
+// keyboard.
+
+const KEYPAD: [usize; 16] =
+[
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF,
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Keymap
+{
+    keys: [char; 16], // keyboard character for each keypad position, same order as KEYPAD
+}
+
+impl Default for Keymap
+{
+    // the layout rusty-8 always had
+    fn default() -> Keymap
+    {
+        Keymap::parse("colemak").unwrap()
+    }
+}
+
+impl Keymap
+{
+    pub const PRESETS: [&'static str; 4] = ["colemak", "qwerty", "azerty", "dvorak"];
+
+    // either a preset name or 16 characters in keypad order
+    pub fn parse(text: &str) -> Option<Keymap>
+    {
+        let layout = match text
+        {
+            "colemak" => "1234qwfparstzxcv",
+            "qwerty" => "1234qwerasdfzxcv",
+            "azerty" => "1234azerqsdfwxcv",
+            "dvorak" => "1234',.paoeu;qjk",
+            _ => text,
+        };
+
+        let chars: Vec<char> = layout.chars().flat_map(char::to_lowercase).collect();
+        if chars.len() != 16
+        {
+            return None;
+        }
+
+        let mut keys = [' '; 16];
+        keys.copy_from_slice(&chars);
+        Some(Keymap { keys })
+    }
+
+    // the keypad key for a keyboard character, if it has one
+    pub fn key(&self, c: char) -> Option<usize>
+    {
+        let c = c.to_lowercase().next()?;
+        self.keys.iter().position(|&k| k == c).map(|i| KEYPAD[i])
+    }
+
+    // the keyboard character for a keypad key
+    pub fn char_for(&self, key: usize) -> Option<char>
+    {
+        KEYPAD.iter().position(|&k| k == key).map(|i| self.keys[i])
+    }
+}
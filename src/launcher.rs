@@ -0,0 +1,384 @@
+// The launcher: a menu of the roms in a directory, with the recently played
+// ones on top. It comes up when rusty-8 is started without a rom or with a
+// directory, and escape (or back on a gamepad) in a game returns to it. A rom
+// dropped onto the window is started right away.
+//
+// Roms are files with a rom extension, files without any extension, and the
+// roms inside zip archives. Their titles come from the rom database as they
+// scroll into view, so a big directory doesn't have to be read (and its
+// cartridges assembled) before the menu shows. The recently played list is
+// kept in ~/.config/rusty-8/recent, one rom per line.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sdl2::controller::Button;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::PixelFormatEnum;
+
+use crate::font::{self, Bitmap};
+use crate::loader::{self, Rom};
+use crate::palette::Palette;
+use crate::romdb::{self, RomDb};
+use crate::scale::ScaleMode;
+use crate::screen::{self, Screen};
+
+const WIDTH: usize = 384; // the menu is drawn at this size and scaled up
+const HEIGHT: usize = 192;
+const ROWS: usize = HEIGHT / font::CELL_HEIGHT;
+const LIST_ROWS: usize = ROWS - 3; // a title line, a blank line and a status line
+const MAX_RECENT: usize = 10;
+
+#[derive(Clone)]
+struct Entry
+{
+    path: String,          // what is passed to the loader
+    name: String,          // the file name
+    title: Option<String>, // the rom database name, or the file name, once it has been shown
+}
+
+enum Item
+{
+    Header(String),
+    Rom(Entry),
+    Note(String),
+}
+
+pub enum Choice
+{
+    Play(String),
+    Quit,
+}
+
+pub struct Launcher
+{
+    dir: PathBuf,
+    db: RomDb,
+    roms: Vec<Entry>,
+    recent: Vec<Entry>,
+    selected: usize, // index into items()
+    scroll: usize,
+    message: Option<String>,
+}
+
+fn recent_file() -> Option<PathBuf>
+{
+    romdb::config_dir().map(|dir| dir.join("recent"))
+}
+
+// "pack.zip:pong.ch8" exists if pack.zip does
+fn exists(path: &str) -> bool
+{
+    let file = loader::split_archive(path).map_or(path, |(archive, _)| archive);
+    Path::new(file).is_file()
+}
+
+fn file_name(path: &Path) -> String
+{
+    path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+fn entry(path: String, name: String) -> Entry
+{
+    Entry { path, name, title: None }
+}
+
+fn scan(dir: &Path) -> Vec<Entry>
+{
+    let mut files: Vec<PathBuf> = match fs::read_dir(dir)
+    {
+        Ok(entries) => entries.filter_map(Result::ok).map(|e| e.path()).filter(|p| p.is_file()).collect(),
+        Err(e) =>
+        {
+            eprintln!("failed to read {}: {}", dir.display(), e);
+            Vec::new()
+        }
+    };
+    files.sort();
+
+    let mut roms = Vec::new();
+    for file in files
+    {
+        let path = file.to_string_lossy().to_string();
+        let name = file_name(&file);
+        if loader::is_archive(&path)
+        {
+            for rom in loader::list_archive(&path).unwrap_or_default()
+            {
+                roms.push(entry(format!("{}:{}", path, rom), format!("{}:{}", name, rom)));
+            }
+        }
+        else if loader::is_rom(&path) || file.extension().is_none()
+        {
+            roms.push(entry(path, name));
+        }
+    }
+    roms
+}
+
+impl Launcher
+{
+    pub fn new(dir: &Path, db: RomDb) -> Launcher
+    {
+        let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        let recent = recent_file().and_then(|file| fs::read_to_string(file).ok()).unwrap_or_default()
+            .lines().filter(|path| exists(path)).take(MAX_RECENT)
+            .map(|path| entry(path.to_string(), file_name(Path::new(path))))
+            .collect();
+
+        let mut launcher = Launcher
+        {
+            roms: scan(&dir),
+            dir,
+            db,
+            recent,
+            selected: 0,
+            scroll: 0,
+            message: None,
+        };
+        launcher.move_selection(0);
+        launcher
+    }
+
+    // a message for the status line, until the selection moves
+    pub fn set_message(& mut self, message: String)
+    {
+        self.message = Some(message);
+    }
+
+    // moves the rom to the top of the recently played list and saves it
+    pub fn played(& mut self, path: &str)
+    {
+        // a dropped rom may not be in the list
+        let played = self.recent.iter().chain(self.roms.iter()).find(|e| e.path == path).cloned()
+            .unwrap_or_else(|| entry(path.to_string(), file_name(Path::new(path))));
+        self.recent.retain(|e| e.path != path);
+        self.recent.insert(0, played);
+        self.recent.truncate(MAX_RECENT);
+        self.selected = 1;
+        self.scroll = 0;
+
+        if let Some(file) = recent_file()
+        {
+            let text: String = self.recent.iter().map(|e| format!("{}\n", e.path)).collect();
+            let result = fs::create_dir_all(file.parent().unwrap()).and_then(|_| fs::write(&file, text));
+            if let Err(e) = result
+            {
+                eprintln!("failed to save {}: {}", file.display(), e);
+            }
+        }
+    }
+
+    fn items(&self) -> Vec<Item>
+    {
+        let mut items = Vec::new();
+        if !self.recent.is_empty()
+        {
+            items.push(Item::Header("recently played".to_string()));
+            items.extend(self.recent.iter().cloned().map(Item::Rom));
+        }
+        items.push(Item::Header(format!("roms in {}", self.dir.display())));
+        if self.roms.is_empty()
+        {
+            items.push(Item::Note("none found".to_string()));
+        }
+        items.extend(self.roms.iter().cloned().map(Item::Rom));
+        items
+    }
+
+    // moves the selection by `delta` roms, skipping headers, and scrolls it
+    // into view
+    fn move_selection(& mut self, delta: isize)
+    {
+        let items = self.items();
+        let roms: Vec<usize> = items.iter().enumerate().filter(|(_, item)| matches!(item, Item::Rom(_)))
+            .map(|(i, _)| i).collect();
+        if roms.is_empty()
+        {
+            self.selected = 0;
+            return;
+        }
+
+        let current = roms.iter().position(|&i| i >= self.selected).unwrap_or(roms.len() - 1) as isize;
+        let next = (current + delta).clamp(0, roms.len() as isize - 1) as usize;
+        self.selected = roms[next];
+
+        // keep the header above the first rom visible
+        if next == 0
+        {
+            self.scroll = 0;
+        }
+        if self.selected < self.scroll
+        {
+            self.scroll = self.selected;
+        }
+        if self.selected >= self.scroll + LIST_ROWS
+        {
+            self.scroll = self.selected + 1 - LIST_ROWS;
+        }
+    }
+
+    // looks up the titles of the roms in view that haven't been shown yet
+    fn look_up_titles(& mut self)
+    {
+        let first_rom = if self.recent.is_empty() { 1 } else { self.recent.len() + 2 };
+        let visible = self.scroll..self.scroll + LIST_ROWS;
+        let db = &self.db;
+        let recent = self.recent.iter_mut().enumerate().map(|(i, entry)| (i + 1, entry));
+        let roms = self.roms.iter_mut().enumerate().map(|(i, entry)| (first_rom + i, entry));
+        for (_, entry) in recent.chain(roms).filter(|(row, _)| visible.contains(row))
+        {
+            if entry.title.is_none()
+            {
+                let title = Rom::open(&entry.path).ok().and_then(|rom| db.settings(&rom).name());
+                entry.title = Some(title.unwrap_or_else(|| entry.name.clone()));
+            }
+        }
+    }
+
+    fn selected_path(&self) -> Option<String>
+    {
+        match self.items().into_iter().nth(self.selected)
+        {
+            Some(Item::Rom(entry)) => Some(entry.path),
+            _ => None,
+        }
+    }
+
+    fn draw(&self, bitmap: &mut Bitmap, palette: &Palette)
+    {
+        let (background, foreground) = (palette.background, palette.foreground);
+        bitmap.fill_rect(0, 0, bitmap.width, bitmap.height, background);
+
+        let columns = WIDTH / font::CELL_WIDTH;
+        let clip = |text: &str| text.chars().take(columns - 1).collect::<String>();
+
+        bitmap.draw_text(font::CELL_WIDTH / 2, 2, "rusty-8", foreground);
+        for (row, item) in self.items().iter().enumerate().skip(self.scroll).take(LIST_ROWS)
+        {
+            let y = (row - self.scroll + 2) * font::CELL_HEIGHT;
+            match *item
+            {
+                Item::Header(ref text) => bitmap.draw_text(font::CELL_WIDTH / 2, y, &clip(text), foreground),
+                // a rom's file name stands in until its title is looked up
+                Item::Note(ref text) | Item::Rom(Entry { title: Some(ref text), .. }) |
+                Item::Rom(Entry { name: ref text, .. }) =>
+                {
+                    let text = clip(&format!("  {}", text));
+                    if row == self.selected
+                    {
+                        bitmap.fill_rect(0, y - 1, WIDTH, font::CELL_HEIGHT, foreground);
+                        bitmap.draw_text(font::CELL_WIDTH / 2, y, &text, background);
+                    }
+                    else
+                    {
+                        bitmap.draw_text(font::CELL_WIDTH / 2, y, &text, foreground);
+                    }
+                },
+            }
+        }
+
+        let status = match self.message
+        {
+            Some(ref message) => message.clone(),
+            None => "enter play   esc quit".to_string(),
+        };
+        bitmap.draw_text(font::CELL_WIDTH / 2, (ROWS - 1) * font::CELL_HEIGHT, &clip(&status), foreground);
+    }
+
+    // shows the menu until a rom is picked or the launcher is closed
+    pub fn show(& mut self, screen: &mut Screen, palette: &Palette) -> Result<Choice, String>
+    {
+        screen.set_title("rusty-8");
+        let texture_creator = screen.canvas.texture_creator();
+        let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, WIDTH as u32,
+            HEIGHT as u32).map_err(|e| e.to_string())?;
+        let mut bitmap = Bitmap::new(WIDTH, HEIGHT);
+        let mut event_pump = screen.sdl.event_pump()?;
+        let mut redraw = true;
+
+        loop
+        {
+            if redraw
+            {
+                self.look_up_titles();
+                self.draw(&mut bitmap, palette);
+                texture.update(None, &bitmap.pixels, WIDTH * 3).map_err(|e| e.to_string())?;
+                screen::draw_texture(&mut screen.canvas, &texture, WIDTH as u32, HEIGHT as u32, ScaleMode::Integer,
+                    palette.background)?;
+                screen.canvas.present();
+                redraw = false;
+            }
+
+            let event = match event_pump.wait_event_timeout(250)
+            {
+                Some(event) => event,
+                None => continue,
+            };
+
+            let delta = match event
+            {
+                Event::Quit { .. } |
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } |
+                Event::ControllerButtonDown { button: Button::Back, .. } => return Ok(Choice::Quit),
+
+                Event::KeyDown { keycode: Some(Keycode::Return), keymod, repeat: false, .. }
+                    if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) =>
+                {
+                    if let Err(e) = screen.toggle_fullscreen()
+                    {
+                        self.set_message(format!("failed to switch fullscreen: {}", e));
+                    }
+                    redraw = true;
+                    0
+                },
+
+                Event::KeyDown { keycode: Some(Keycode::Return), .. } |
+                Event::KeyDown { keycode: Some(Keycode::KpEnter), .. } |
+                Event::ControllerButtonDown { button: Button::A, .. } |
+                Event::ControllerButtonDown { button: Button::Start, .. } =>
+                {
+                    match self.selected_path()
+                    {
+                        Some(path) => return Ok(Choice::Play(path)),
+                        None => 0,
+                    }
+                },
+
+                Event::KeyDown { keycode: Some(Keycode::Up), .. } |
+                Event::ControllerButtonDown { button: Button::DPadUp, .. } => -1,
+                Event::KeyDown { keycode: Some(Keycode::Down), .. } |
+                Event::ControllerButtonDown { button: Button::DPadDown, .. } => 1,
+                Event::KeyDown { keycode: Some(Keycode::PageUp), .. } |
+                Event::ControllerButtonDown { button: Button::LeftShoulder, .. } => -(LIST_ROWS as isize),
+                Event::KeyDown { keycode: Some(Keycode::PageDown), .. } |
+                Event::ControllerButtonDown { button: Button::RightShoulder, .. } => LIST_ROWS as isize,
+                Event::KeyDown { keycode: Some(Keycode::Home), .. } => isize::MIN / 2,
+                Event::KeyDown { keycode: Some(Keycode::End), .. } => isize::MAX / 2,
+
+                Event::DropFile { filename, .. } => return Ok(Choice::Play(filename)),
+
+                Event::ControllerDeviceAdded { which, .. } =>
+                {
+                    screen.add_controller(which);
+                    0
+                },
+                Event::Window { win_event: WindowEvent::SizeChanged(..), .. } |
+                Event::Window { win_event: WindowEvent::Exposed, .. } =>
+                {
+                    redraw = true;
+                    0
+                },
+                _ => 0,
+            };
+
+            if delta != 0
+            {
+                self.message = None;
+                self.move_selection(delta);
+                redraw = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,412 @@
+// The lint subcommand: goes through the code the analysis finds reachable
+// for what tends to break when a rom moves between interpreters. That's
+// instructions whose behavior depends on a quirk, jumps to odd addresses or
+// out of the rom, code that can't be reached, writes into the font area or
+// over the program itself, and sprites drawn past the edge of the screen.
+// Where the code gives a hint of the quirks it was written for (SUPER-CHIP
+// programs shift with 8X06, tables are walked with FX65 after FX65, ...) it's
+// counted towards a preset to recommend.
+//
+// It's all guesswork from constants: what depends on values only known when
+// the rom runs isn't flagged.
+
+use std::collections::HashSet;
+
+use crate::analysis::{self, Analysis};
+use crate::cpu::Quirks;
+use crate::disasm;
+
+// instructions followed after a load or store to see what's done with I next
+const LOOKAHEAD: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Category
+{
+    Quirks,
+    Jumps,
+    Unreachable,
+    FontWrites,
+    SelfModifying,
+    OffScreen,
+}
+
+impl Category
+{
+    pub const ALL: [Category; 6] = [Category::Quirks, Category::Jumps, Category::Unreachable, Category::FontWrites,
+        Category::SelfModifying, Category::OffScreen];
+
+    pub fn heading(self) -> &'static str
+    {
+        match self
+        {
+            Category::Quirks => "depends on quirks",
+            Category::Jumps => "jumps",
+            Category::Unreachable => "unreachable",
+            Category::FontWrites => "writes into the font area",
+            Category::SelfModifying => "self-modifying code",
+            Category::OffScreen => "draws off the screen",
+        }
+    }
+}
+
+pub struct Finding
+{
+    pub category: Category,
+    pub address: u16,
+    pub message: String,
+}
+
+// places where the code looks written for a quirk being on, and off
+#[derive(Clone, Copy, Default)]
+struct Votes
+{
+    on: usize,
+    off: usize,
+}
+
+pub struct Lint
+{
+    pub analysis: Analysis,
+    pub findings: Vec<Finding>,
+    shift_vy: Votes,
+    load_store_increment_i: Votes,
+    jump_vx: Votes,
+}
+
+// the instructions run after `address` on the way out of it, as far as
+// straight line code and jumps go, for what's done next with I or VF
+fn following(analysis: &Analysis, bytes: &[u8], address: u16) -> Vec<(u16, u16)>
+{
+    let mut instructions = Vec::new();
+    let mut seen = HashSet::new();
+    let mut address = address;
+    while instructions.len() < LOOKAHEAD
+    {
+        address = match analysis.successors.get(&address).and_then(|s| s.first())
+        {
+            Some(&(to, analysis::Edge::Next)) | Some(&(to, analysis::Edge::Jump)) if seen.insert(to) => to,
+            _ => break,
+        };
+        match Analysis::opcode(bytes, analysis.load_address, address)
+        {
+            Some(opcode) => instructions.push((address, opcode)),
+            None => break,
+        }
+    }
+    instructions
+}
+
+// whether an instruction reads VF, and whether it sets it
+fn uses_vf(opcode: u16) -> (bool, bool)
+{
+    let x = (opcode & 0x0F00) >> 8 == 0xF;
+    let y = (opcode & 0x00F0) >> 4 == 0xF;
+    match disasm::pattern(opcode)
+    {
+        "3XNN" | "4XNN" | "FX15" | "FX18" | "FX1E" | "FX29" | "FX33" | "EX9E" | "EXA1" => (x, false),
+        "5XY0" | "9XY0" => (x || y, false),
+        "7XNN" => (x, x),
+        "6XNN" | "CXNN" | "FX07" | "FX0A" => (false, x),
+        "8XY0" => (y, x),
+        "8XY1" | "8XY2" | "8XY3" | "8XY4" | "8XY5" | "8XY7" | "8XY6" | "8XYE" => (x || y, true),
+        "DXYN" => (x || y, true),
+        "FX55" => ((opcode & 0x0F00) == 0x0F00, false),
+        "FX65" => (false, (opcode & 0x0F00) == 0x0F00),
+        _ => (false, false),
+    }
+}
+
+fn describe(opcode: u16) -> String
+{
+    format!("{:04X} {}", opcode, disasm::disassemble(opcode))
+}
+
+impl Lint
+{
+    pub fn run(bytes: &[u8], load_address: u16) -> Lint
+    {
+        let mut lint = Lint
+        {
+            analysis: analysis::analyze(bytes, load_address),
+            findings: Vec::new(),
+            shift_vy: Votes::default(),
+            load_store_increment_i: Votes::default(),
+            jump_vx: Votes::default(),
+        };
+        let code = lint.analysis.code_bytes();
+
+        let instructions: Vec<_> = lint.analysis.states.iter().map(|(&a, &s)| (a, s)).collect();
+        for (address, state) in instructions
+        {
+            if let Some(opcode) = Analysis::opcode(bytes, load_address, address)
+            {
+                lint.check(bytes, &code, address, opcode, &state);
+            }
+        }
+        for &address in &lint.analysis.invalid
+        {
+            let opcode = Analysis::opcode(bytes, load_address, address).unwrap_or(0);
+            lint.findings.push(Finding { category: Category::Jumps, address,
+                message: format!("{:04X} isn't an instruction, but the code runs into it", opcode) });
+        }
+        lint.unreachable(bytes, &code);
+        lint.findings.sort_by_key(|f| f.address);
+        lint
+    }
+
+    fn add(& mut self, category: Category, address: u16, message: String)
+    {
+        self.findings.push(Finding { category, address, message });
+    }
+
+    fn check(& mut self, bytes: &[u8], code: &[bool], address: u16, opcode: u16, state: &analysis::State)
+    {
+        let x = ((opcode & 0x0F00) >> 8) as usize;
+        let y = ((opcode & 0x00F0) >> 4) as usize;
+        let n = opcode & 0x000F;
+        let nnn = opcode & 0x0FFF;
+        let instruction = describe(opcode);
+
+        match disasm::pattern(opcode)
+        {
+            "8XY6" | "8XYE" if x != y =>
+            {
+                self.add(Category::Quirks, address, format!("{}: shifts V{:X} with shift_vy, V{:X} itself without",
+                    instruction, y, x));
+                // SUPER-CHIP code leaves Y at 0, it isn't used there
+                if y == 0
+                {
+                    self.shift_vy.off += 1;
+                }
+                else
+                {
+                    self.shift_vy.on += 1;
+                }
+            },
+            "BNNN" if x != 0 =>
+            {
+                self.add(Category::Quirks, address, format!("{}: jumps to 0x{:03X} + V0, or with jump_vx to 0x{:03X} \
+                    + V{:X}", instruction, nnn, nnn, x));
+                match (state.v[0].is_some(), state.v[x].is_some())
+                {
+                    (true, false) => self.jump_vx.off += 1,
+                    (false, true) => self.jump_vx.on += 1,
+                    _ => {},
+                }
+            },
+            "FX55" | "FX65" =>
+            {
+                // it only matters if I is used before it's set again
+                let next = following(&self.analysis, bytes, address).into_iter()
+                    .find(|&(_, o)| ["ANNN", "FX29", "FX1E", "FX55", "FX65", "FX33", "DXYN"].contains(&disasm::pattern(o)));
+                if let Some((used, next)) = next.filter(|&(_, o)| !["ANNN", "FX29"].contains(&disasm::pattern(o)))
+                {
+                    self.add(Category::Quirks, address, format!("{}: moves I past V{:X} with load_store_increment_i, \
+                        which {:03X} then uses", instruction, x, used));
+                    match disasm::pattern(next)
+                    {
+                        "FX55" | "FX65" => self.load_store_increment_i.on += 1,
+                        "FX1E" => self.load_store_increment_i.off += 1,
+                        _ => {},
+                    }
+                }
+            },
+            "8XY1" | "8XY2" | "8XY3" if x != 0xF =>
+            {
+                let read = following(&self.analysis, bytes, address).into_iter()
+                    .map(|(a, o)| (a, uses_vf(o)))
+                    .take_while(|&(_, (read, written))| read || !written)
+                    .find(|&(_, (read, _))| read);
+                if let Some((read, _)) = read
+                {
+                    self.add(Category::Quirks, address, format!("{}: clears VF with vf_reset, which {:03X} then reads",
+                        instruction, read));
+                }
+            },
+            _ => {},
+        }
+
+        match disasm::pattern(opcode)
+        {
+            // roms written to run at odd addresses throughout get to jump between them
+            "1NNN" | "2NNN" if nnn % 2 == 1 && address.is_multiple_of(2) =>
+            {
+                self.add(Category::Jumps, address, format!("{}: goes to an odd address", instruction));
+            },
+            "1NNN" | "2NNN" if !self.analysis.in_rom(nnn) =>
+            {
+                self.add(Category::Jumps, address, format!("{}: goes outside the rom", instruction));
+            },
+            _ => {},
+        }
+
+        // what FX55 and FX33 write, where I is known
+        let written = match (disasm::pattern(opcode), state.i)
+        {
+            ("FX55", Some(i)) => Some(i..=i + x as u16),
+            ("FX33", Some(i)) => Some(i..=i + 2),
+            _ => None,
+        };
+        if let Some(written) = written
+        {
+            if *written.start() < 0x200
+            {
+                self.add(Category::FontWrites, address, format!("{}: writes 0x{:03X}-0x{:03X}", instruction,
+                    written.start(), written.end()));
+            }
+            let overwritten = written.clone().find(|&a| code.get(a as usize) == Some(&true));
+            if let Some(overwritten) = overwritten
+            {
+                self.add(Category::SelfModifying, address, format!("{}: writes 0x{:03X}-0x{:03X}, over the code at \
+                    0x{:03X}", instruction, written.start(), written.end(), overwritten));
+            }
+        }
+
+        if disasm::pattern(opcode) == "DXYN"
+        {
+            if let (Some(vx), Some(vy)) = (state.v[x], state.v[y])
+            {
+                let (vx, vy) = (vx as u16, vy as u16);
+                let rows = if n == 0 { 16 } else { n };
+                let problem = if vx >= 64 || vy >= 32
+                {
+                    Some("starts off the screen, interpreters differ on wrapping it")
+                }
+                else if vx + 8 > 64 || vy + rows > 32
+                {
+                    Some("goes past the edge, interpreters differ on clipping or wrapping it")
+                }
+                else
+                {
+                    None
+                };
+                if let Some(problem) = problem
+                {
+                    self.add(Category::OffScreen, address, format!("{}: draws at {},{}, {}", instruction, vx, vy, problem));
+                }
+            }
+        }
+    }
+
+    // the rom outside the reachable code, in runs, leaving out data, that is
+    // runs that an LD I points into, and the zeros roms are often padded with
+    fn unreachable(& mut self, bytes: &[u8], code: &[bool])
+    {
+        let load_address = self.analysis.load_address;
+        let referenced: Vec<u16> = self.analysis.states.iter()
+            .filter_map(|(&a, s)| Analysis::opcode(bytes, load_address, a).map(|o| (o, s)))
+            .flat_map(|(o, s)| vec![Some(o & 0x0FFF).filter(|_| disasm::pattern(o) == "ANNN"), s.i])
+            .flatten()
+            .collect();
+
+        let mut address = load_address as usize;
+        let end = self.analysis.end as usize;
+        while address < end
+        {
+            if code[address]
+            {
+                address += 1;
+                continue;
+            }
+            let start = address;
+            while address < end && !code[address]
+            {
+                address += 1;
+            }
+            let run = &bytes[start - load_address as usize..address - load_address as usize];
+            let data = referenced.iter().any(|&r| (start..address).contains(&(r as usize)));
+            if !data && run.iter().any(|&b| b != 0)
+            {
+                let mut message = format!("0x{:03X}-0x{:03X}, {} bytes, isn't run or pointed to by LD I", start,
+                    address - 1, run.len());
+                if !self.analysis.indirect.is_empty()
+                {
+                    message.push_str(", unless a JP V0 goes there");
+                }
+                self.add(Category::Unreachable, start as u16, message);
+            }
+        }
+    }
+
+    pub fn depends_on_quirks(&self) -> bool
+    {
+        self.findings.iter().any(|f| f.category == Category::Quirks)
+    }
+
+    // how well a set of quirks agrees with the hints
+    pub fn score(&self, quirks: Quirks) -> i64
+    {
+        let votes = [self.shift_vy, self.load_store_increment_i, self.jump_vx];
+        let settings = [quirks.shift_vy, quirks.load_store_increment_i, quirks.jump_vx];
+        settings.iter().zip(votes.iter())
+            .map(|(&on, v)| if on { v.on as i64 - v.off as i64 } else { v.off as i64 - v.on as i64 })
+            .sum()
+    }
+
+    // the preset that agrees best with the hints, None if there aren't any.
+    // Of presets that do as well, the one the rom runs with now is kept.
+    pub fn recommend(&self, current: Quirks) -> Option<&'static str>
+    {
+        let votes = [self.shift_vy, self.load_store_increment_i, self.jump_vx];
+        if votes.iter().all(|v| v.on == 0 && v.off == 0)
+        {
+            return None;
+        }
+        let mut best: Option<(&'static str, i64)> = None;
+        for &name in Quirks::PRESETS.iter()
+        {
+            let quirks = Quirks::preset(name).unwrap();
+            let score = self.score(quirks);
+            let better = match best
+            {
+                None => true,
+                Some((_, best_score)) => score > best_score || (score == best_score && quirks == current),
+            };
+            if better
+            {
+                best = Some((name, score));
+            }
+        }
+        best.map(|(name, _)| name)
+    }
+
+    // why the recommendation is what it is, a line per quirk with hints
+    pub fn hints(&self) -> Vec<String>
+    {
+        [("shift_vy", self.shift_vy), ("load_store_increment_i", self.load_store_increment_i), ("jump_vx", self.jump_vx)]
+            .iter()
+            .filter(|(_, v)| v.on + v.off > 0)
+            .map(|(name, v)| format!("{:<23} {} place{} for on, {} for off", name, v.on, if v.on == 1 { "" } else { "s" },
+                v.off))
+            .collect()
+    }
+
+    pub fn findings(&self, category: Category) -> impl Iterator<Item = &Finding>
+    {
+        self.findings.iter().filter(move |f| f.category == category)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{Category, Lint};
+    use crate::analysis::Edge;
+
+    #[test]
+    fn decodes_what_the_vm_runs()
+    {
+        // 5011 is run as 5010, 0123 faults and 00E0 clears the screen
+        let rom = [0x50, 0x11, 0x01, 0x23, 0x00, 0xE0, 0x12, 0x06];
+        let lint = Lint::run(&rom, 0x200);
+
+        assert_eq!(lint.analysis.successors[&0x200], [(0x202, Edge::Next), (0x204, Edge::Skip)]);
+        assert_eq!(lint.analysis.invalid, [0x202]);
+        assert!(lint.analysis.successors[&0x202].is_empty());
+        assert_eq!(lint.analysis.successors[&0x204], [(0x206, Edge::Next)]);
+
+        let invalid: Vec<_> = lint.findings(Category::Jumps).map(|f| (f.address, f.message.as_str())).collect();
+        assert_eq!(invalid, [(0x202, "0123 isn't an instruction, but the code runs into it")]);
+        assert!(lint.findings.iter().all(|f| f.address == 0x202));
+    }
+}
@@ -0,0 +1,281 @@
+// Reads rom files and works out which platform they were written for. The
+// VM side of loading (checking that the rom fits and copying it into memory)
+// is VM::load_bytes.
+//
+// A rom can be given as
+//
+//   pong.ch8                 a file
+//   -                        standard input
+//   pack.zip                 the only rom in a zip archive
+//   pack.zip:games/pong.ch8  a file in a zip archive
+//
+// Any of these can be an Octo cartridge (.gif), which is assembled and brings
+// its own run options along.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use zip::ZipArchive;
+
+use crate::cartridge;
+use crate::cpu::Quirks;
+use crate::romdb::RomInfo;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Platform
+{
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform
+{
+    // by file extension: .sc8 is SUPER-CHIP, .xo8 is XO-CHIP, anything else
+    // (.ch8, .c8 or no extension at all) is plain CHIP-8
+    pub fn from_path(path: &Path) -> Platform
+    {
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref()
+        {
+            Some("sc8") => Platform::SuperChip,
+            Some("xo8") => Platform::XoChip,
+            _ => Platform::Chip8,
+        }
+    }
+
+    // the names used by the rom database and the quirk presets
+    pub fn parse(name: &str) -> Option<Platform>
+    {
+        match name
+        {
+            "chip8" => Some(Platform::Chip8),
+            "schip" => Some(Platform::SuperChip),
+            "xochip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str
+    {
+        match *self
+        {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    // plain CHIP-8 roms get the quirks rusty-8 always used, the others the
+    // quirks of their interpreter
+    pub fn quirks(&self) -> Quirks
+    {
+        match *self
+        {
+            Platform::Chip8 => Quirks::default(),
+            _ => Quirks::preset(self.name()).unwrap(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError
+{
+    Io(String, io::Error),
+    Empty,
+    TooBig { size: usize, available: usize },
+    BadLoadAddress(u16),
+    Archive(String, String),         // archive name and what is wrong with it
+    NotInArchive(String, String),    // archive name and the missing file
+    NoRomInArchive(String),
+    AmbiguousArchive(String, Vec<String>), // archive name and the roms to choose from
+    Cartridge(String, String),       // cartridge name and why it can't be loaded
+}
+
+impl fmt::Display for LoadError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            LoadError::Io(ref name, ref e) => write!(f, "{}: {}", name, e),
+            LoadError::Empty => write!(f, "the rom is empty"),
+            LoadError::TooBig { size, available } =>
+                write!(f, "the rom is {} bytes but only {} fit in memory", size, available),
+            LoadError::BadLoadAddress(address) => write!(f, "can't load a rom at 0x{:X}", address),
+            LoadError::Archive(ref name, ref e) => write!(f, "{}: {}", name, e),
+            LoadError::NotInArchive(ref name, ref file) => write!(f, "{}: there is no {} in it", name, file),
+            LoadError::NoRomInArchive(ref name) => write!(f, "{}: there are no files in it", name),
+            LoadError::AmbiguousArchive(ref name, ref roms) =>
+                write!(f, "{} has {} roms, pick one with {}:<rom>", name, roms.len(), name),
+            LoadError::Cartridge(ref name, ref e) => write!(f, "{}: {}", name, e),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Rom
+{
+    pub name: String, // file name, used for captures and messages
+    pub bytes: Vec<u8>,
+    pub platform: Platform,
+    pub info: RomInfo, // settings that came with the rom, for octo cartridges
+}
+
+impl Rom
+{
+    // the name picks the platform, and whether the bytes are a cartridge
+    pub fn from_bytes(name: &str, bytes: Vec<u8>) -> Result<Rom, LoadError>
+    {
+        let (bytes, info) = if cartridge::is_cartridge(name)
+        {
+            let cartridge = cartridge::read(&bytes).map_err(|e| LoadError::Cartridge(name.to_string(), e))?;
+            (cartridge.program, cartridge.info)
+        }
+        else
+        {
+            (bytes, RomInfo::default())
+        };
+
+        Ok(Rom
+        {
+            name: name.to_string(),
+            bytes,
+            platform: Platform::from_path(Path::new(name)),
+            info,
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<Rom, LoadError>
+    {
+        let bytes = fs::read(path).map_err(|e| LoadError::Io(path.display().to_string(), e))?;
+        Rom::from_bytes(&path.to_string_lossy(), bytes)
+    }
+
+    // a file, "-" for standard input, or a rom in a zip archive
+    pub fn open(name: &str) -> Result<Rom, LoadError>
+    {
+        if name == "-"
+        {
+            let mut bytes = Vec::new();
+            io::stdin().read_to_end(&mut bytes).map_err(|e| LoadError::Io("standard input".to_string(), e))?;
+            return Rom::from_bytes(name, bytes);
+        }
+
+        match split_archive(name)
+        {
+            Some((archive, Some(file))) => Rom::read_archive(archive, file),
+            Some((archive, None)) => match list_archive(archive)?.as_slice()
+            {
+                [] => Err(LoadError::NoRomInArchive(archive.to_string())),
+                [file] => Rom::read_archive(archive, file),
+                roms => Err(LoadError::AmbiguousArchive(archive.to_string(), roms.to_vec())),
+            },
+            None => Rom::read(Path::new(name)),
+        }
+    }
+
+    fn read_archive(archive: &str, file: &str) -> Result<Rom, LoadError>
+    {
+        let mut zip = open_archive(archive)?;
+        let mut entry = match zip.by_name(file)
+        {
+            Ok(entry) => entry,
+            Err(zip::result::ZipError::FileNotFound) =>
+                return Err(LoadError::NotInArchive(archive.to_string(), file.to_string())),
+            Err(e) => return Err(LoadError::Archive(archive.to_string(), e.to_string())),
+        };
+
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes).map_err(|e| LoadError::Io(format!("{}:{}", archive, file), e))?;
+        Rom::from_bytes(&format!("{}:{}", archive, file), bytes)
+    }
+}
+
+pub fn is_archive(name: &str) -> bool
+{
+    name.to_lowercase().ends_with(".zip")
+}
+
+pub fn is_rom(name: &str) -> bool
+{
+    matches!(Path::new(name).extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref(),
+        Some("ch8") | Some("c8") | Some("sc8") | Some("xo8") | Some("gif"))
+}
+
+// "pack.zip:pong.ch8" is Some(("pack.zip", Some("pong.ch8"))), "pack.zip" is
+// Some(("pack.zip", None)) and anything else is not an archive
+pub fn split_archive(name: &str) -> Option<(&str, Option<&str>)>
+{
+    if is_archive(name)
+    {
+        return Some((name, None));
+    }
+    name.match_indices(':').map(|(i, _)| i).find(|&i| is_archive(&name[..i]))
+        .map(|i| (&name[..i], Some(&name[i + 1..])))
+}
+
+fn open_archive(archive: &str) -> Result<ZipArchive<File>, LoadError>
+{
+    let file = File::open(archive).map_err(|e| LoadError::Io(archive.to_string(), e))?;
+    ZipArchive::new(file).map_err(|e| LoadError::Archive(archive.to_string(), e.to_string()))
+}
+
+// the roms in a zip archive, sorted. Files with a rom extension if there are
+// any, otherwise every file, since plenty of roms have no extension at all.
+pub fn list_archive(archive: &str) -> Result<Vec<String>, LoadError>
+{
+    let zip = open_archive(archive)?;
+    let mut files: Vec<String> = zip.file_names().filter(|f| !f.ends_with('/')).map(String::from).collect();
+    if files.iter().any(|f| is_rom(f))
+    {
+        files.retain(|f| is_rom(f));
+    }
+    files.sort();
+    Ok(files)
+}
+
+// watches the file a rom came from by polling its modification time, for
+// --watch. A rom in an archive is watched through the archive.
+pub struct Watcher
+{
+    path: PathBuf,
+    loaded: Option<SystemTime>, // the time of the file that was loaded
+    seen: Option<SystemTime>,   // the time at the last poll
+}
+
+fn modified(path: &Path) -> Option<SystemTime>
+{
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl Watcher
+{
+    // None for standard input, which can't change
+    pub fn new(name: &str) -> Option<Watcher>
+    {
+        if name == "-"
+        {
+            return None;
+        }
+        let path = PathBuf::from(split_archive(name).map_or(name, |(archive, _)| archive));
+        let loaded = modified(&path);
+        Some(Watcher { path, loaded, seen: loaded })
+    }
+
+    // true once the file has changed since it was loaded and then stayed the
+    // same for a poll, so a rom isn't read while it's still being written
+    pub fn changed(& mut self) -> bool
+    {
+        let time = modified(&self.path);
+        let settled = time.is_some() && time == self.seen && time != self.loaded;
+        self.seen = time;
+        if settled
+        {
+            self.loaded = time;
+        }
+        settled
+    }
+}
@@ -25,209 +25,1114 @@
 // Copyright (C) 2019 Daniel Pistelli / ntcore.com
 ///////////////////////////////////////////////////////////////////////////////
 
-extern crate sdl2;
-
-use std::path::Path;
-use sdl2::rect::Rect;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::PixelFormatEnum;
-use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioSpecWAV, AudioCVT};
-
+mod analysis;
+mod beeper;
+mod cartridge;
+mod cfg;
+mod cli;
+mod coverage;
 mod cpu;
+#[cfg(feature = "sdl")]
+mod debug_window;
+mod disasm;
+#[cfg(feature = "sdl")]
+mod font;
+mod frontend;
+mod keymap;
+#[cfg(feature = "sdl")]
+mod launcher;
+mod lint;
+mod loader;
+mod movie;
+mod octo;
+#[cfg(feature = "sdl")]
+mod osd;
+mod palette;
+mod profile;
+mod recorder;
+mod romdb;
+mod scale;
+#[cfg(feature = "sdl")]
+mod screen;
+mod screenshot;
+mod term;
+mod terminal;
+mod trace;
+mod tui;
+mod video;
+#[cfg(feature = "sdl")]
+mod window;
+use std::collections::{BTreeSet, VecDeque};
 use std::env;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+use cli::{Command, RunOptions, TraceDiffOptions};
+use coverage::Coverage;
+use cpu::Fault;
+use frontend::Headless;
+#[cfg(feature = "sdl")]
+use frontend::Exit;
+use keymap::Keymap;
+#[cfg(feature = "sdl")]
+use launcher::{Choice, Launcher};
+use lint::Lint;
+use loader::{LoadError, Platform, Rom};
+use movie::{Movie, MovieHeader};
+use palette::Palette;
+use profile::Profile;
+use recorder::Recorder;
+use romdb::{RomDb, RomInfo};
+#[cfg(feature = "sdl")]
+use screen::Screen;
+use trace::{Entry, Trace};
+use video::VideoOut;
 
 const SCREEN_WIDTH: u32 = 64;
 const SCREEN_HEIGHT: u32 = 32;
 
-const DISPLAY_MODIFIER: u32 = 10;
+// the VM runs in 60 Hz frames: a batch of instructions followed by one timer tick
+const CYCLES_PER_FRAME: u32 = 10;
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
 
-const DISPLAY_WIDTH: u32 = SCREEN_WIDTH * DISPLAY_MODIFIER;
-const DISPLAY_HEIGHT: u32 = SCREEN_HEIGHT * DISPLAY_MODIFIER;
+// a minute of emulated time, unless bench is given --frames
+const BENCH_FRAMES: u64 = 3600;
 
-struct Sound {
-    data: Vec<u8>,
-    volume: f32,
-    pos: usize,
-}
+// --watch looks at the rom file twice a second
+const WATCH_INTERVAL: u64 = 30;
 
-impl AudioCallback for Sound {
-    type Channel = u8;
+// exit status, see the end of cli::USAGE
+const EXIT_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_LOAD_FAILED: i32 = 3;
+const EXIT_VM_FAULT: i32 = 4;
+
+// per run state around the VM: speed, input movie and everything that wants
+// to see each emulated frame
+struct Session
+{
+    frame: u64,
+    cycles_per_frame: u32,
+    cycle: u32, // instructions run of the current frame, when a step or a breakpoint stopped partway
+    paused: bool,
+    breakpoints: BTreeSet<u16>, // tui's, run_frame stops before running the instruction at one
+    resumed: bool,              // the next instruction runs even if it has a breakpoint
+    movie: Option<Movie>,
+    recorder: Option<Recorder>,
+    video: Option<VideoOut>,
+    trace: Option<Trace>,
+    profile: Option<Profile>,
+    coverage: Option<Coverage>,
+}
 
-    fn callback(&mut self, out: &mut [u8]) {
-        for dst in out.iter_mut() {
-            *dst = (*self.data.get(self.pos).unwrap_or(&0) as f32 * self.volume) as u8;
-            self.pos += 1;
+impl Session
+{
+    fn new(cycles_per_frame: u32) -> Session
+    {
+        Session
+        {
+            frame: 0,
+            cycles_per_frame,
+            cycle: 0,
+            paused: false,
+            breakpoints: BTreeSet::new(),
+            resumed: false,
+            movie: None,
+            recorder: None,
+            video: None,
+            trace: None,
+            profile: None,
+            coverage: None,
         }
     }
 }
 
 fn main()
 {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2
+    let command = match cli::parse(env::args().skip(1).collect())
     {
-        println!("syntax: rusty-8 [rom_file]");
-        return;
-    }
+        Ok(command) => command,
+        Err(e) =>
+        {
+            eprintln!("rusty-8: {}", e);
+            eprintln!("try 'rusty-8 --help'");
+            process::exit(EXIT_USAGE);
+        }
+    };
+
+    let status = match command
+    {
+        Command::Help => { print!("{}", cli::USAGE); 0 },
+        Command::Version => { println!("rusty-8 {}", cli::VERSION); 0 },
+        Command::Disasm(options) => disasm(&options),
+        Command::Info(options) => info(&options),
+        Command::Lint(options) => lint(&options),
+        Command::Cfg(options) => cfg(&options),
+        Command::Bench(options) => bench(&options),
+        Command::Tui(options) => tui(&options),
+        Command::TraceDiff(options) => tracediff(&options),
+        Command::Run(ref options) if Path::new(&options.rom_file).is_dir() => launch(options),
+        Command::Run(options) => run(&options),
+    };
+    process::exit(status);
+}
+
+// reads the rom and loads it into a fresh VM, asking which one to run if it is
+// an archive with more than one
+fn load_rom(options: &RunOptions) -> Result<(cpu::VM, Rom), LoadError>
+{
     let mut vm = cpu::VM::new();
-    if !vm.load_application(&args[1])
+    let rom = match vm.load_application(&options.rom_file, options.load_address)
     {
-        println!("failed to load rom");
-        return
+        Err(LoadError::AmbiguousArchive(archive, roms)) => match choose_rom(&archive, &roms)
+        {
+            Some(rom) => vm.load_application(&format!("{}:{}", archive, rom), options.load_address),
+            None => Err(LoadError::AmbiguousArchive(archive, roms)),
+        },
+        result => result,
+    }?;
+    Ok((vm, rom))
+}
+
+// lists the roms of an archive and reads the number of one from the terminal
+fn choose_rom(archive: &str, roms: &[String]) -> Option<String>
+{
+    if !io::stdin().is_terminal()
+    {
+        return None;
     }
 
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let audio_subsystem = sdl_context.audio().unwrap();
-    let window = video_subsystem.window("rusty-8", DISPLAY_WIDTH, DISPLAY_HEIGHT).position_centered().build()
-        .map_err(|e| e.to_string()).unwrap();
+    eprintln!("{} has {} roms:", archive, roms.len());
+    for (i, rom) in roms.iter().enumerate()
+    {
+        eprintln!("{:4}  {}", i + 1, rom);
+    }
+    loop
+    {
+        eprint!("run which one (1-{}, enter to cancel)? ", roms.len());
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line).ok()? == 0 || line.trim().is_empty()
+        {
+            return None;
+        }
+        match line.trim().parse::<usize>()
+        {
+            Ok(n) if n >= 1 && n <= roms.len() => return Some(roms[n - 1].clone()),
+            _ => eprintln!("'{}' is not one of the roms", line.trim()),
+        }
+    }
+}
 
-    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string()).unwrap();
-    let texture_creator = canvas.texture_creator();
+// quirks when the command line doesn't pick any: the rom database entry, its
+// platform, or the platform the file extension says
+fn default_quirks(rom_info: &RomInfo, rom: &Rom) -> cpu::Quirks
+{
+    rom_info.quirks
+        .or_else(|| rom_info.platform.as_deref().and_then(Platform::parse).map(|p| p.quirks()))
+        .unwrap_or_else(|| rom.platform.quirks())
+}
 
-    let mut texture  = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH,
-        SCREEN_HEIGHT).map_err(|e| e.to_string()).unwrap();
+fn disasm(options: &RunOptions) -> i32
+{
+    let rom = match Rom::open(&options.rom_file)
+    {
+        Ok(rom) => rom,
+        Err(e) =>
+        {
+            eprintln!("failed to load rom {}", e);
+            return EXIT_LOAD_FAILED;
+        }
+    };
 
-    // TODO: bring er back in
-    let mut _audio_device = None;
-    let has_sound = Path::new("beep.wav").exists();
+    for (i, word) in rom.bytes.chunks(2).enumerate()
+    {
+        let address = options.load_address as usize + i * 2;
+        match *word
+        {
+            [high, low] =>
+            {
+                let opcode = (high as u16) << 8 | low as u16;
+                println!("{:03X}: {:04X}  {}", address, opcode, disasm::disassemble(opcode));
+            },
+            _ => println!("{:03X}: {:02X}    DB 0x{:02X}", address, word[0], word[0]),
+        }
+    }
+    0
+}
 
-    let mut timer = 0;
+// the built in rom database with the user's on top, and --rom-db on top of that
+fn load_rom_db(rom_db: Option<&str>) -> RomDb
+{
+    let mut db = RomDb::embedded();
+    for path in RomDb::user_file().into_iter().chain(rom_db.map(PathBuf::from))
+    {
+        if let Err(e) = db.merge_file(&path)
+        {
+            eprintln!("ignoring rom database {}", e);
+        }
+    }
+    db
+}
 
-    'mainloop: loop
+fn info(options: &RunOptions) -> i32
+{
+    let rom = match Rom::open(&options.rom_file)
     {
-        for event in sdl_context.event_pump().unwrap().poll_iter()
+        Ok(rom) => rom,
+        Err(LoadError::AmbiguousArchive(archive, roms)) =>
         {
-            match event
+            println!("archive:  {}", archive);
+            for rom in roms
             {
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } |
-                Event::Quit { .. } => break 'mainloop,
+                println!("          {}", rom);
+            }
+            return 0;
+        },
+        Err(e) =>
+        {
+            eprintln!("failed to load rom {}", e);
+            return EXIT_LOAD_FAILED;
+        }
+    };
 
-                // keydown
-                Event::KeyDown { keycode: Some(Keycode::Num1), .. } => { vm.key[1] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::Num2), .. } => { vm.key[2] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::Num3), .. } => { vm.key[3] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::Num4), .. } => { vm.key[0xC] = 1; },
+    println!("file:     {}", rom.name);
+    println!("size:     {} bytes", rom.bytes.len());
+    let rom_hash = romdb::rom_hash(&rom.bytes);
+    println!("sha1:     {}", rom_hash);
+    println!("platform: {}", rom.platform.name());
+    println!("load at:  0x{:03X}", options.load_address);
+    match cpu::VM::new().load_bytes(&rom.bytes, options.load_address)
+    {
+        Ok(()) => println!("free:     {} bytes", 4096 - options.load_address as usize - rom.bytes.len()),
+        Err(e) => println!("free:     none, {}", e),
+    }
 
-                // Mapped to colemak layout
-                Event::KeyDown { keycode: Some(Keycode::Q), .. } => { vm.key[4] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::W), .. } => { vm.key[5] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::F), .. } => { vm.key[6] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::P), .. } => { vm.key[0xD] = 1; },
+    let db = load_rom_db(options.rom_db.as_deref());
+    if db.lookup(&rom_hash).is_none()
+    {
+        println!("not in the rom database");
+    }
 
-                Event::KeyDown { keycode: Some(Keycode::A), .. } => { vm.key[7] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::R), .. } => { vm.key[8] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::S), .. } => { vm.key[9] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::T), .. } => { vm.key[0xE] = 1; },
+    let rom_info = db.settings(&rom);
+    let keymap = Keymap::default();
+    let fields = [
+        ("title:", rom_info.title.clone()),
+        ("author:", rom_info.author.clone()),
+        ("platform:", rom_info.platform.clone()),
+        ("ipf:", rom_info.cycles_per_frame.map(|n| n.to_string())),
+        ("quirks:", rom_info.quirks.map(|q| q.to_string())),
+        ("keys:", Some(rom_info.key_hints(&keymap)).filter(|s| !s.is_empty())),
+        ("palette:", rom_info.palette.map(|p| p.to_string())),
+    ];
+    for (name, value) in fields.iter()
+    {
+        if let Some(value) = value
+        {
+            println!("{:<9} {}", name, value);
+        }
+    }
+    0
+}
 
-                Event::KeyDown { keycode: Some(Keycode::Z), .. } => { vm.key[0xA] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::X), .. } => { vm.key[0] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::C), .. } => { vm.key[0xB] = 1; },
-                Event::KeyDown { keycode: Some(Keycode::V), .. } => { vm.key[0xF] = 1; },
+fn lint(options: &RunOptions) -> i32
+{
+    let rom = match load_rom(options)
+    {
+        Ok((_, rom)) => rom,
+        Err(e) =>
+        {
+            eprintln!("failed to load rom {}", e);
+            return EXIT_LOAD_FAILED;
+        }
+    };
 
-                // key up
-                Event::KeyUp { keycode: Some(Keycode::Num1), .. } => { vm.key[1] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::Num2), .. } => { vm.key[2] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::Num3), .. } => { vm.key[3] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::Num4), .. } => { vm.key[0xC] = 0; },
+    let lint = Lint::run(&rom.bytes, options.load_address);
+    println!("{}: {} instructions reachable from 0x{:03X}", rom.name, lint.analysis.states.len(), options.load_address);
+    for &category in lint::Category::ALL.iter()
+    {
+        let mut findings = lint.findings(category).peekable();
+        if findings.peek().is_some()
+        {
+            println!("\n{}:", category.heading());
+        }
+        for finding in findings
+        {
+            println!("  {:03X}: {}", finding.address, finding.message);
+        }
+    }
 
-                Event::KeyUp { keycode: Some(Keycode::Q), .. } => { vm.key[4] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::W), .. } => { vm.key[5] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::F), .. } => { vm.key[6] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::P), .. } => { vm.key[0xD] = 0; },
+    let db = load_rom_db(options.rom_db.as_deref());
+    let quirks = default_quirks(&db.settings(&rom), &rom);
+    let preset = |quirks: cpu::Quirks| cpu::Quirks::PRESETS.iter().copied().find(|&name| cpu::Quirks::preset(name) == Some(quirks));
+    let recommended = lint.recommend(quirks);
+    println!();
+    match recommended
+    {
+        Some(name) =>
+        {
+            println!("recommended quirks: {}", name);
+            for hint in lint.hints()
+            {
+                println!("  {}", hint);
+            }
+        },
+        None if lint.depends_on_quirks() => println!("recommended quirks: none, the code doesn't hint at any"),
+        None => println!("recommended quirks: any, nothing found depends on them"),
+    }
+    println!("runs with: {}", preset(quirks).map(|name| name.to_string()).unwrap_or_else(|| quirks.to_string()));
+    if let Some(name) = recommended.filter(|&name| lint.score(cpu::Quirks::preset(name).unwrap()) > lint.score(quirks))
+    {
+        println!("try --quirks {}", name);
+    }
+    0
+}
 
-                Event::KeyUp { keycode: Some(Keycode::A), .. } => { vm.key[7] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::R), .. } => { vm.key[8] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::S), .. } => { vm.key[9] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::T), .. } => { vm.key[0xE] = 0; },
+// the control flow graph of the rom as DOT, on stdout
+fn cfg(options: &RunOptions) -> i32
+{
+    let rom = match load_rom(options)
+    {
+        Ok((_, rom)) => rom,
+        Err(e) =>
+        {
+            eprintln!("failed to load rom {}", e);
+            return EXIT_LOAD_FAILED;
+        }
+    };
 
-                Event::KeyUp { keycode: Some(Keycode::Z), .. } => { vm.key[0xA] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::X), .. } => { vm.key[0] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::C), .. } => { vm.key[0xB] = 0; },
-                Event::KeyUp { keycode: Some(Keycode::V), .. } => { vm.key[0xF] = 0; },
+    let analysis = analysis::analyze(&rom.bytes, options.load_address);
+    match cfg::write_dot(&mut io::stdout().lock(), &analysis, &rom.bytes, &rom.name)
+    {
+        Ok(()) => 0,
+        Err(e) =>
+        {
+            eprintln!("failed to write the graph: {}", e);
+            EXIT_ERROR
+        }
+    }
+}
 
-                _ => {}
+// runs the rom as fast as possible, without a window or any captures
+fn bench(options: &RunOptions) -> i32
+{
+    let (mut vm, rom) = match load_rom(options)
+    {
+        Ok(loaded) => loaded,
+        Err(e) =>
+        {
+            eprintln!("failed to load rom {}", e);
+            return EXIT_LOAD_FAILED;
+        }
+    };
+    let rom_info = load_rom_db(options.rom_db.as_deref()).settings(&rom);
+
+    vm.quirks = options.quirks.unwrap_or_else(|| default_quirks(&rom_info, &rom));
+    vm.seed_rng(options.seed.unwrap_or(0));
+
+    let mut session = Session::new(options.cycles_per_frame.or(rom_info.cycles_per_frame).unwrap_or(CYCLES_PER_FRAME));
+    let frames = options.headless.unwrap_or(BENCH_FRAMES);
+
+    let start = Instant::now();
+    let mut status = 0;
+    while session.frame < frames
+    {
+        if let Err(fault) = run_frame(&mut vm, &mut session)
+        {
+            eprintln!("vm fault: {}", fault);
+            status = EXIT_VM_FAULT;
+            break;
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64().max(1e-9);
+
+    let instructions = session.frame * session.cycles_per_frame as u64;
+    println!("rom:          {}", options.rom_file);
+    println!("frames:       {} ({:.1} s emulated)", session.frame, session.frame as f64 / 60.0);
+    println!("instructions: {}", instructions);
+    println!("elapsed:      {:.3} s", elapsed);
+    println!("speed:        {:.0} instructions/s, {:.1}x real time", instructions as f64 / elapsed,
+        session.frame as f64 / 60.0 / elapsed);
+    status
+}
+
+// a loaded rom with everything needed to run it
+struct Game
+{
+    vm: cpu::VM,
+    rom: Rom,
+    rom_info: RomInfo,
+    palette: Palette,
+    keymap: Keymap,
+    session: Session,
+}
+
+// loads the rom and sets up the VM, the movie and the captures. Errors come
+// with the exit status to use.
+fn start(options: &RunOptions) -> Result<Game, (i32, String)>
+{
+    let (mut vm, rom) = match load_rom(options)
+    {
+        Ok(loaded) => loaded,
+        Err(e) => return Err((EXIT_LOAD_FAILED, format!("failed to load rom {}", e))),
+    };
+
+    // the command line overrides the rom database, which overrides the options
+    // of a cartridge, which override the defaults
+    let rom_hash = romdb::rom_hash(&rom.bytes);
+    let rom_info = load_rom_db(options.rom_db.as_deref()).settings(&rom);
+    let palette = options.palette.or(rom_info.palette).unwrap_or_default();
+    let keymap = options.keymap.unwrap_or_default();
+    if let Some(name) = rom_info.name()
+    {
+        eprintln!("{}", name);
+    }
+    if !rom_info.keys.is_empty()
+    {
+        eprintln!("keys: {}", rom_info.key_hints(&keymap));
+    }
+
+    let mut header = MovieHeader
+    {
+        rom_hash,
+        load_address: options.load_address,
+        seed: options.seed.unwrap_or_else(rand::random),
+        quirks: options.quirks.unwrap_or_else(|| default_quirks(&rom_info, &rom)),
+        cycles_per_frame: options.cycles_per_frame.or(rom_info.cycles_per_frame).unwrap_or(CYCLES_PER_FRAME),
+    };
+
+    // a replay brings its own settings, which have to match the recording exactly
+    let mut movie = None;
+    if let Some(ref file_name) = options.replay
+    {
+        match Movie::load(Path::new(file_name))
+        {
+            Ok((recorded, _)) if recorded.rom_hash != header.rom_hash =>
+            {
+                return Err((EXIT_ERROR, format!("{} was recorded with a different rom ({})", file_name, recorded.rom_hash)));
+            },
+            Ok((recorded, _)) if recorded.load_address != header.load_address =>
+            {
+                return Err((EXIT_ERROR, format!("{} was recorded with the rom loaded at 0x{:03X}, use --load-address \
+                    0x{:03X}", file_name, recorded.load_address, recorded.load_address)));
+            },
+            Ok((recorded, replay)) =>
+            {
+                header = recorded;
+                movie = Some(replay);
+            },
+            Err(e) => return Err((EXIT_ERROR, format!("failed to load movie {}: {}", file_name, e))),
+        }
+    }
+    else if let Some(ref file_name) = options.record_input
+    {
+        match Movie::record(Path::new(file_name), &header)
+        {
+            Ok(recording) => movie = Some(recording),
+            Err(e) => eprintln!("failed to record input to {}: {}", file_name, e),
+        }
+    }
+
+    vm.quirks = header.quirks;
+    vm.seed_rng(header.seed);
+
+    let mut session = Session::new(header.cycles_per_frame);
+    session.paused = options.paused && options.headless.is_none();
+    session.movie = movie;
+    session.recorder = options.record.as_ref().and_then(|file_name| start_recording(file_name, options, &palette));
+    session.video = options.video_out.as_ref().and_then(|file_name| start_video(file_name, options, &palette));
+    session.trace = options.trace.as_ref().and_then(|file_name| start_trace(file_name, options));
+    if options.profile.is_some() || options.profile_folded.is_some()
+    {
+        session.profile = Some(Profile::new(options.load_address));
+    }
+    if options.coverage.is_some() || options.coverage_report.is_some()
+    {
+        session.coverage = Some(Coverage::new());
+    }
+
+    Ok(Game { vm, rom, rom_info, palette, keymap, session })
+}
+
+impl Game
+{
+    // resets the VM and loads `rom` into it, picking up the settings of the
+    // rom just like start does. A movie can't follow a reset, so it ends here.
+    fn restart(& mut self, options: &RunOptions, rom: Rom, hard: bool) -> Result<(), LoadError>
+    {
+        // make sure the rom fits before touching the running VM
+        cpu::VM::new().load_bytes(&rom.bytes, options.load_address)?;
+        let rom_info = load_rom_db(options.rom_db.as_deref()).settings(&rom);
+
+        self.vm.reset(options.load_address);
+        if hard
+        {
+            self.vm.clear_memory();
+        }
+        self.vm.load_bytes(&rom.bytes, options.load_address)?;
+        self.vm.quirks = options.quirks.unwrap_or_else(|| default_quirks(&rom_info, &rom));
+
+        self.stop_movie("the reset");
+
+        self.session.cycle = 0;
+        self.session.cycles_per_frame = options.cycles_per_frame.or(rom_info.cycles_per_frame).unwrap_or(CYCLES_PER_FRAME);
+        self.palette = options.palette.or(rom_info.palette).unwrap_or_default();
+        self.rom = rom;
+        self.rom_info = rom_info;
+        Ok(())
+    }
+
+    fn stop_movie(& mut self, reason: &str)
+    {
+        if let Some(movie) = self.session.movie.take()
+        {
+            eprintln!("input movie stopped by {}", reason);
+            if let Err(e) = movie.finish(self.session.frame)
+            {
+                eprintln!("failed to finish input recording: {}", e);
             }
         }
+    }
 
-        if timer == 2000
+    // reads the rom again, so changes to the file are picked up. Standard
+    // input can only be read once, so that keeps the bytes it had.
+    fn reload(& mut self, options: &RunOptions, hard: bool) -> Result<(), LoadError>
+    {
+        let rom = match self.rom.name.as_str()
         {
-            vm.emulate_cycle();
-            timer = 0;
+            "-" => self.rom.clone(),
+            name => Rom::open(name)?,
+        };
+        self.restart(options, rom, hard)
+    }
+
+    // picks up a rom that changed on disk. With --watch-keep only the program
+    // bytes are swapped, so registers and the rest of memory carry on.
+    fn hot_reload(& mut self, options: &RunOptions) -> Result<(), LoadError>
+    {
+        if !options.watch_keep
+        {
+            return self.reload(options, true);
         }
-        else
+        let rom = Rom::open(&self.rom.name)?;
+
+        // what's left of a longer old program is cleared, it fit so this does too
+        let mut program = rom.bytes.clone();
+        program.resize(program.len().max(self.rom.bytes.len()), 0);
+        self.vm.replace_program(&program, options.load_address)?;
+        self.vm.draw_flag = true;
+
+        // a movie is only good for the rom it was recorded with
+        self.stop_movie("the reload");
+        self.rom = rom;
+        Ok(())
+    }
+}
+
+// the terminal debugger, see tui.rs. It starts paused, at the first instruction.
+fn tui(options: &RunOptions) -> i32
+{
+    if !io::stdout().is_terminal() || !io::stdin().is_terminal()
+    {
+        eprintln!("rusty-8: tui needs a terminal");
+        return EXIT_USAGE;
+    }
+
+    let mut game = match start(options)
+    {
+        Ok(game) => game,
+        Err((status, message)) =>
         {
-            timer += 1;
+            eprintln!("{}", message);
+            return status;
         }
+    };
+    game.session.paused = true;
+    game.session.breakpoints = options.breakpoints.iter().cloned().collect();
 
-        if vm.draw_flag
+    match tui::run(&mut game, options)
+    {
+        Ok((_, result)) => finish(game, options, result),
+        Err(e) =>
         {
-            texture.with_lock(None, |buffer: &mut [u8], pitch: usize|
+            eprintln!("{}", e);
+            finish(game, options, Ok(()));
+            EXIT_ERROR
+        }
+    }
+}
+
+// the next entry of a trace, with the file name on errors
+fn next_entry(reader: &mut trace::Reader, path: &str) -> Result<Option<Entry>, String>
+{
+    reader.next().transpose().map_err(|e| format!("failed to load trace {}: {}", path, e))
+}
+
+// one trace around the instruction where the two differ, which is marked
+fn print_trace_context(path: &str, before: impl Iterator<Item = Entry>, at: Option<Entry>, after: &[Entry])
+{
+    println!("{}:", path);
+    for entry in before
+    {
+        println!("    {}", entry.text());
+    }
+    match at
+    {
+        Some(entry) => println!("  > {}", entry.text()),
+        None => println!("  > (end of trace)"),
+    }
+    for entry in after
+    {
+        println!("    {}", entry.text());
+    }
+}
+
+// goes through two traces side by side up to the first instruction where
+// they differ, and shows it. True when they don't.
+fn compare_traces(options: &TraceDiffOptions) -> Result<bool, String>
+{
+    let [ref path_a, ref path_b] = options.traces;
+    let open = |path: &str| trace::Reader::open(path).map_err(|e| format!("failed to load trace {}: {}", path, e));
+    let (mut trace_a, mut trace_b) = (open(path_a)?, open(path_b)?);
+
+    let mut before = VecDeque::new();
+    let mut count: u64 = 0;
+    let (at_a, at_b, fields) = loop
+    {
+        match (next_entry(&mut trace_a, path_a)?, next_entry(&mut trace_b, path_b)?)
+        {
+            (None, None) =>
+            {
+                println!("the traces match, {} instructions", count);
+                return Ok(true);
+            },
+            (Some(a), Some(b)) =>
             {
-                for y in 0..SCREEN_HEIGHT as usize
+                let fields = a.differences(&b, options.timers);
+                if !fields.is_empty()
                 {
-                    for x in 0..SCREEN_WIDTH as usize
-                    {
-                        let offset: usize = y*pitch + x*3;
-                        let mut color: u8 = 0;
-                        if vm.gfx[((y * SCREEN_WIDTH as usize) + x) as usize] != 0
-                        {
-                            color = 255;
-                        }
-                        buffer[offset] = color;
-                        buffer[offset + 1] = color;
-                        buffer[offset + 2] = color;
-                    }
+                    break (Some(a), Some(b), fields.join(", "));
                 }
-            }).unwrap();
+                before.push_back((a, b));
+                if before.len() > options.context
+                {
+                    before.pop_front();
+                }
+                count += 1;
+            },
+            (a, b) => break (a, b, "one ends first".to_string()),
+        }
+    };
 
-            canvas.clear();
-            canvas.copy(&texture, None, Some(Rect::new(0, 0, DISPLAY_WIDTH, DISPLAY_HEIGHT))).unwrap();
-            canvas.present();
+    let mut after = (Vec::new(), Vec::new());
+    for _ in 0..options.context
+    {
+        after.0.extend(next_entry(&mut trace_a, path_a)?);
+        after.1.extend(next_entry(&mut trace_b, path_b)?);
+    }
+
+    println!("the traces differ at instruction {}: {}", count, fields);
+    print_trace_context(path_a, before.iter().map(|&(a, _)| a), at_a, &after.0);
+    print_trace_context(path_b, before.iter().map(|&(_, b)| b), at_b, &after.1);
+    Ok(false)
+}
 
-            vm.draw_flag = false;
+fn tracediff(options: &TraceDiffOptions) -> i32
+{
+    match compare_traces(options)
+    {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) =>
+        {
+            eprintln!("{}", e);
+            EXIT_LOAD_FAILED
         }
+    }
+}
 
-        if vm.beep_flag
+// closes the captures and works out the exit status
+fn finish(game: Game, options: &RunOptions, result: Result<(), Fault>) -> i32
+{
+    // whatever was captured up to a fault is still worth keeping
+    let session = game.session;
+    if let Some(movie) = session.movie
+    {
+        if let Err(e) = movie.finish(session.frame)
+        {
+            eprintln!("failed to finish input recording: {}", e);
+        }
+    }
+    stop_recording(session.recorder);
+    if let Some(video) = session.video
+    {
+        if let Err(e) = video.finish()
+        {
+            eprintln!("failed to finish video stream: {}", e);
+        }
+    }
+    if let Some(trace) = session.trace
+    {
+        if let Err(e) = trace.finish()
+        {
+            eprintln!("failed to finish trace: {}", e);
+        }
+    }
+    if let Some(profile) = session.profile
+    {
+        for (file_name, folded) in [(&options.profile, false), (&options.profile_folded, true)]
         {
-            if has_sound
+            if let Some(file_name) = file_name
             {
-                let desired_spec = AudioSpecDesired
+                if let Err(e) = profile.save(file_name, folded)
                 {
-                    freq: Some(44_100),
-                    channels: Some(1), // mono
-                    samples: None,
-                };
-
-                _audio_device = Some(Box::new(audio_subsystem.open_playback(None, &desired_spec, |spec|
-                {
-                    let wav = AudioSpecWAV::load_wav("beep.wav").expect("could not load test WAV file");
-                    let cvt = AudioCVT::new(wav.format, wav.channels, wav.freq, spec.format,
-                        spec.channels, spec.freq).expect("could not convert WAV file");
-                    let data = cvt.convert(wav.buffer().to_vec());
-
-                    // initialize the audio callback
-                    Sound
-                    {
-                        data: data,
-                        volume: 0.25,
-                        pos: 0,
-                    }
-                }).unwrap()));
-
-                // start playback
-                if let Some(ref dev) = _audio_device
-                {
-                    dev.resume();
+                    eprintln!("failed to save profile {}: {}", file_name, e);
                 }
             }
-            else
+        }
+    }
+
+    if let Some(coverage) = session.coverage
+    {
+        save_coverage(&coverage, &game.vm, &game.rom, options);
+    }
+
+    if let Some(ref file_name) = options.screenshot
+    {
+        save_screenshot(&game.vm, &game.palette, file_name, options.display_modifier);
+    }
+
+    match result
+    {
+        Ok(()) => 0,
+        Err(fault) =>
+        {
+            eprintln!("vm fault: {}", fault);
+            EXIT_VM_FAULT
+        }
+    }
+}
+
+fn run(options: &RunOptions) -> i32
+{
+    if options.terminal && options.headless.is_none()
+    {
+        if let Err(e) = terminal::check()
+        {
+            eprintln!("rusty-8: {}", e);
+            return EXIT_USAGE;
+        }
+    }
+
+    let mut game = match start(options)
+    {
+        Ok(game) => game,
+        Err((status, message)) =>
+        {
+            eprintln!("{}", message);
+            return status;
+        }
+    };
+
+    let outcome = match options.headless
+    {
+        Some(frames) => frontend::run(&mut game, options, &mut Headless::new(frames)),
+        None if options.terminal => terminal::run(&mut game, options),
+        #[cfg(feature = "sdl")]
+        None => match Screen::open(SCREEN_WIDTH * options.display_modifier, SCREEN_HEIGHT * options.display_modifier,
+            options.fullscreen)
+        {
+            Ok(mut screen) => window::run(&mut game, options, &mut screen),
+            Err(e) =>
+            {
+                eprintln!("failed to open the window: {}", e);
+                return EXIT_ERROR;
+            }
+        },
+        #[cfg(not(feature = "sdl"))]
+        None => unreachable!("without sdl everything runs in the terminal"),
+    };
+    match outcome
+    {
+        Ok((_, result)) => finish(game, options, result),
+        Err(e) =>
+        {
+            eprintln!("{}", e);
+            finish(game, options, Ok(()));
+            EXIT_ERROR
+        }
+    }
+}
+
+// the launcher for a directory of roms, coming back to it whenever a rom is left
+#[cfg(feature = "sdl")]
+fn launch(options: &RunOptions) -> i32
+{
+    if options.headless.is_some() || options.terminal
+    {
+        eprintln!("rusty-8: {} is a directory, the launcher needs a window", options.rom_file);
+        return EXIT_USAGE;
+    }
+
+    let mut launcher = Launcher::new(Path::new(&options.rom_file), load_rom_db(options.rom_db.as_deref()));
+    let mut screen = match Screen::open(SCREEN_WIDTH * options.display_modifier,
+        SCREEN_HEIGHT * options.display_modifier, options.fullscreen)
+    {
+        Ok(screen) => screen,
+        Err(e) =>
+        {
+            eprintln!("failed to open the window: {}", e);
+            return EXIT_ERROR;
+        }
+    };
+    let palette = options.palette.unwrap_or_default();
+
+    loop
+    {
+        let path = match launcher.show(&mut screen, &palette)
+        {
+            Ok(Choice::Play(path)) => path,
+            Ok(Choice::Quit) => return 0,
+            Err(e) =>
+            {
+                eprintln!("launcher error: {}", e);
+                return EXIT_ERROR;
+            }
+        };
+
+        let rom_options = RunOptions { rom_file: path.clone(), ..options.clone() };
+        let mut game = match start(&rom_options)
+        {
+            Ok(game) => game,
+            Err((_, message)) =>
+            {
+                eprintln!("{}", message);
+                launcher.set_message(message);
+                continue;
+            }
+        };
+        launcher.played(&path);
+
+        let (exit, result) = match window::run(&mut game, &rom_options, &mut screen)
+        {
+            Ok(outcome) => outcome,
+            Err(e) =>
+            {
+                eprintln!("{}", e);
+                finish(game, &rom_options, Ok(()));
+                return EXIT_ERROR;
+            }
+        };
+        if let Err(ref fault) = result
+        {
+            launcher.set_message(format!("vm fault: {}", fault));
+        }
+        finish(game, &rom_options, result);
+        if let Exit::Quit = exit
+        {
+            return 0;
+        }
+    }
+}
+
+
+#[cfg(not(feature = "sdl"))]
+fn launch(options: &RunOptions) -> i32
+{
+    eprintln!("rusty-8: {} is a directory, the launcher needs a build with sdl", options.rom_file);
+    EXIT_USAGE
+}
+
+// runs the VM to the end of its current 60 Hz frame, all of it unless steps
+// ran the start already. A breakpoint pauses the session before its
+// instruction and leaves the frame unfinished.
+fn run_frame(vm: &mut cpu::VM, session: &mut Session) -> Result<(), Fault>
+{
+    let frame = session.frame;
+    while session.frame == frame
+    {
+        if !session.resumed && session.breakpoints.contains(&vm.registers().pc)
+        {
+            session.paused = true;
+            return Ok(());
+        }
+        run_instruction(vm, session)?;
+    }
+    Ok(())
+}
+
+// runs a single instruction, tracing and profiling it first if asked to. The
+// first of a frame gets the keys of a replayed movie and the last one ends
+// the frame.
+fn run_instruction(vm: &mut cpu::VM, session: &mut Session) -> Result<(), Fault>
+{
+    if session.cycle == 0
+    {
+        if let Some(ref mut movie) = session.movie
+        {
+            if movie.end() == Some(session.frame)
+            {
+                eprintln!("replay finished at frame {}", session.frame);
+                session.movie = None;
+            }
+            else if let Err(e) = movie.apply(session.frame, &mut vm.key)
             {
-                println!("BEEP");
+                eprintln!("input recording failed, stopping: {}", e);
+                session.movie = None;
             }
+        }
+    }
+
+    if let Some(ref mut profile) = session.profile
+    {
+        profile.record(vm);
+    }
+    if let Some(ref mut coverage) = session.coverage
+    {
+        coverage.record(vm);
+    }
+    if let Some(ref mut trace) = session.trace
+    {
+        if let Err(e) = trace.record(vm)
+        {
+            eprintln!("trace failed, stopping: {}", e);
+            session.trace = None;
+        }
+    }
+    vm.emulate_cycle()?;
+    session.resumed = false;
+
+    session.cycle += 1;
+    if session.cycle >= session.cycles_per_frame
+    {
+        session.cycle = 0;
+        end_frame(vm, session);
+    }
+    Ok(())
+}
 
-            vm.beep_flag = false;
+// ticks the timers and hands the finished frame to the captures, if any
+fn end_frame(vm: &mut cpu::VM, session: &mut Session)
+{
+    session.frame += 1;
+
+    // sampled before the tick, so that even a one frame beep is heard
+    let sound_on = vm.sound_playing();
+    vm.tick_timers();
+
+    if let Some(ref mut rec) = session.recorder
+    {
+        if let Err(e) = rec.capture(&vm.gfx)
+        {
+            eprintln!("recording failed, stopping: {}", e);
+            session.recorder = None;
         }
     }
+
+    if let Some(ref mut video) = session.video
+    {
+        if let Err(e) = video.write_frame(&vm.gfx, sound_on)
+        {
+            eprintln!("video stream failed, stopping: {}", e);
+            session.video = None;
+        }
+    }
+}
+
+fn start_trace(file_name: &str, options: &RunOptions) -> Option<Trace>
+{
+    match Trace::create(file_name, options.trace_range.clone(), !options.trace_paused)
+    {
+        Ok(trace) => Some(trace),
+        Err(e) =>
+        {
+            eprintln!("failed to open trace {}: {}", file_name, e);
+            None
+        }
+    }
+}
+
+fn save_coverage(coverage: &Coverage, vm: &cpu::VM, rom: &Rom, options: &RunOptions)
+{
+    let load_address = options.load_address as usize;
+    if let Some(ref file_name) = options.coverage
+    {
+        match coverage.save_json(file_name, &rom.name, options.load_address, rom.bytes.len())
+        {
+            Ok(()) => eprintln!("saved coverage {}", file_name),
+            Err(e) => eprintln!("failed to save coverage {}: {}", file_name, e),
+        }
+    }
+    if let Some(ref file_name) = options.coverage_report
+    {
+        if let Err(e) = coverage.save_report(file_name, vm, &rom.name, load_address..load_address + rom.bytes.len())
+        {
+            eprintln!("failed to save coverage report {}: {}", file_name, e);
+        }
+    }
+}
+
+fn start_video(file_name: &str, options: &RunOptions, palette: &Palette) -> Option<VideoOut>
+{
+    // unless told otherwise the audio goes next to the video, e.g. clip.y4m -> clip.wav
+    let audio_path = match options.audio_out
+    {
+        Some(ref audio_out) => Some(PathBuf::from(audio_out)),
+        None if file_name != "-" => Some(Path::new(file_name).with_extension("wav")),
+        None => None,
+    };
+
+    // the soundtrack sounds like the game would, muted included
+    let volume = if options.mute { 0.0 } else { options.volume as f32 / 100.0 };
+    match VideoOut::create(file_name, SCREEN_WIDTH as usize, SCREEN_HEIGHT as usize, palette,
+        options.video_scale as usize, audio_path.as_deref(), volume)
+    {
+        Ok(video) => Some(video),
+        Err(e) =>
+        {
+            eprintln!("failed to open video stream {}: {}", file_name, e);
+            None
+        }
+    }
+}
+
+fn start_recording(file_name: &str, options: &RunOptions, palette: &Palette) -> Option<Recorder>
+{
+    match Recorder::create(Path::new(file_name), SCREEN_WIDTH as usize, SCREEN_HEIGHT as usize, palette,
+        options.display_modifier as usize)
+    {
+        Ok(recorder) =>
+        {
+            eprintln!("recording to {}", file_name);
+            Some(recorder)
+        },
+        Err(e) =>
+        {
+            eprintln!("failed to record to {}: {}", file_name, e);
+            None
+        }
+    }
+}
+
+fn stop_recording(recorder: Option<Recorder>)
+{
+    if let Some(recorder) = recorder
+    {
+        let frames = recorder.frames();
+        match recorder.finish()
+        {
+            Ok(()) => eprintln!("recorded {} frames", frames),
+            Err(e) => eprintln!("failed to finish recording: {}", e),
+        }
+    }
+}
+
+fn save_screenshot(vm: &cpu::VM, palette: &Palette, file_name: &str, scale: u32)
+{
+    match screenshot::save_png(Path::new(file_name), &vm.gfx, SCREEN_WIDTH as usize, SCREEN_HEIGHT as usize,
+        palette, scale as usize)
+    {
+        Ok(()) => eprintln!("saved screenshot {}", file_name),
+        Err(e) => eprintln!("failed to save screenshot {}: {}", file_name, e),
+    }
 }
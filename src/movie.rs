@@ -0,0 +1,234 @@
+// Input movies: every change of the keypad, tagged with the frame it happened
+// on, plus everything else a run depends on (rom, rng seed, quirks, speed).
+// Replaying a movie on the same rom gives exactly the same run.
+//
+// The file is plain text:
+//
+//   rusty-8 movie 1
+//   rom 9d7e5c7b...          sha-1 of the rom
+//   load_address 0x200       where it was loaded, 0x200 if not given
+//   seed 1234
+//   quirks shift_vy=0 load_store_increment_i=1 jump_vx=0 vf_reset=0
+//   cycles_per_frame 10
+//   120 0020                 from frame 120 on, key 5 is down
+//   131 0000                 from frame 131 on, no key is down
+//   end 600                  the recording stopped at frame 600
+
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::cpu::Quirks;
+
+const MAGIC: &str = "rusty-8 movie 1";
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct MovieHeader
+{
+    pub rom_hash: String,
+    pub load_address: u16,
+    pub seed: u64,
+    pub quirks: Quirks,
+    pub cycles_per_frame: u32,
+}
+
+pub enum Movie
+{
+    Recording
+    {
+        out: BufWriter<File>,
+        last: u16,
+    },
+    Replaying
+    {
+        events: Vec<(u64, u16)>,
+        next: usize,
+        end: Option<u64>,
+    },
+}
+
+fn invalid(message: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// one bit per key, key 0 is the lowest bit
+fn key_mask(key: &[u8; 16]) -> u16
+{
+    key.iter().enumerate().fold(0, |mask, (i, &k)| if k != 0 { mask | 1 << i } else { mask })
+}
+
+impl Movie
+{
+    pub fn record(path: &Path, header: &MovieHeader) -> io::Result<Movie>
+    {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{}", MAGIC)?;
+        writeln!(out, "rom {}", header.rom_hash)?;
+        writeln!(out, "load_address 0x{:03X}", header.load_address)?;
+        writeln!(out, "seed {}", header.seed)?;
+        writeln!(out, "quirks {}", header.quirks)?;
+        writeln!(out, "cycles_per_frame {}", header.cycles_per_frame)?;
+
+        Ok(Movie::Recording
+        {
+            out,
+            last: 0,
+        })
+    }
+
+    pub fn load(path: &Path) -> io::Result<(MovieHeader, Movie)>
+    {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        if lines.next().transpose()?.as_deref() != Some(MAGIC)
+        {
+            return Err(invalid("not a rusty-8 movie"));
+        }
+
+        let mut rom_hash = None;
+        let mut load_address = None;
+        let mut seed = None;
+        let mut quirks = None;
+        let mut cycles_per_frame = None;
+        let mut events = Vec::new();
+        let mut end = None;
+
+        for line in lines
+        {
+            let line = line?;
+            let mut parts = line.trim().splitn(2, ' ');
+            let (name, value) = match (parts.next(), parts.next())
+            {
+                (Some(name), Some(value)) => (name, value.trim()),
+                _ if line.trim().is_empty() => continue,
+                _ => return Err(invalid(&format!("bad movie line: {}", line))),
+            };
+
+            match name
+            {
+                "rom" => rom_hash = Some(value.to_string()),
+                "load_address" => load_address = Some(value.strip_prefix("0x").and_then(|v| u16::from_str_radix(v, 16).ok())
+                    .ok_or_else(|| invalid(&format!("bad movie line: {}", line)))?),
+                "seed" => seed = value.parse().ok(),
+                "quirks" => quirks = Quirks::parse(value),
+                "cycles_per_frame" => cycles_per_frame = value.parse().ok(),
+                "end" => end = value.parse().ok(),
+                frame =>
+                {
+                    let frame = frame.parse().map_err(|_| invalid(&format!("bad movie line: {}", line)))?;
+                    let mask = u16::from_str_radix(value, 16).map_err(|_| invalid(&format!("bad movie line: {}", line)))?;
+                    events.push((frame, mask));
+                },
+            }
+        }
+
+        let header = MovieHeader
+        {
+            rom_hash: rom_hash.ok_or_else(|| invalid("movie has no rom hash"))?,
+            // movies from before the load address could be changed
+            load_address: load_address.unwrap_or(0x200),
+            seed: seed.ok_or_else(|| invalid("movie has no seed"))?,
+            quirks: quirks.ok_or_else(|| invalid("movie has no quirks"))?,
+            cycles_per_frame: cycles_per_frame.ok_or_else(|| invalid("movie has no cycles_per_frame"))?,
+        };
+
+        events.sort_by_key(|&(frame, _)| frame);
+        Ok((header, Movie::Replaying
+        {
+            events,
+            next: 0,
+            end,
+        }))
+    }
+
+    // call at the start of every frame, before the VM runs. A recording notes
+    // the keys if they changed, a replay overwrites them with the recorded ones.
+    pub fn apply(& mut self, frame: u64, key: &mut [u8; 16]) -> io::Result<()>
+    {
+        match *self
+        {
+            Movie::Recording { ref mut out, ref mut last } =>
+            {
+                let mask = key_mask(key);
+                if mask != *last
+                {
+                    writeln!(out, "{} {:04X}", frame, mask)?;
+                    *last = mask;
+                }
+            },
+            Movie::Replaying { ref events, ref mut next, .. } =>
+            {
+                let mut mask = if *next > 0 { events[*next - 1].1 } else { 0 };
+                while *next < events.len() && events[*next].0 <= frame
+                {
+                    mask = events[*next].1;
+                    *next += 1;
+                }
+                for (i, k) in key.iter_mut().enumerate()
+                {
+                    *k = ((mask >> i) & 1) as u8;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // the frame a replay runs out of input, if the movie says
+    pub fn end(&self) -> Option<u64>
+    {
+        match *self
+        {
+            Movie::Recording { .. } => None,
+            Movie::Replaying { end, .. } => end,
+        }
+    }
+
+    pub fn finish(self, frame: u64) -> io::Result<()>
+    {
+        if let Movie::Recording { mut out, .. } = self
+        {
+            writeln!(out, "end {}", frame)?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::fs;
+    use std::path::Path;
+
+    use super::Movie;
+    use crate::cpu::VM;
+    use crate::{romdb, run_frame, Session};
+
+    // the screen at the end of data/movies/tetris.movie, a few pieces dropped,
+    // moved and rotated; it changes if anything a replay depends on does
+    const TETRIS_SCREEN: &str = "fafece703725e0b0bdbc1a9e61fad817b2f85682";
+
+    #[test]
+    fn tetris_replay()
+    {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let rom = fs::read(root.join("tetris.c8")).unwrap();
+        let (header, replay) = Movie::load(&root.join("data/movies/tetris.movie")).unwrap();
+        assert_eq!(header.rom_hash, romdb::rom_hash(&rom));
+
+        let mut vm = VM::new();
+        vm.load_bytes(&rom, header.load_address).unwrap();
+        vm.quirks = header.quirks;
+        vm.seed_rng(header.seed);
+        let end = replay.end().unwrap();
+        let mut session = Session::new(header.cycles_per_frame);
+        session.movie = Some(replay);
+        while session.frame < end
+        {
+            run_frame(&mut vm, &mut session).unwrap();
+        }
+
+        assert_eq!(romdb::rom_hash(&vm.gfx), TETRIS_SCREEN);
+    }
+}
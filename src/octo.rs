@@ -0,0 +1,937 @@
+// An assembler for Octo, the CHIP-8 assembly language of John Earnest's Octo
+// IDE. Octo cartridges carry source code rather than bytes, so this is what
+// turns them into a rom. It covers the language as the Octo manual describes
+// it: the CHIP-8, SUPER-CHIP and XO-CHIP statements, structured if/loop
+// blocks, labels, :const, :alias, :macro, :calc, :byte, :org, :next, :unpack
+// and :pointer. :stringmode and :assert are not supported.
+//
+// :calc expressions work like Octo's: every binary operator has the same
+// precedence and they group from the right, so { 2 * 3 + 1 } is 8.
+
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts;
+
+const START: usize = 0x200;
+
+#[derive(Clone)]
+struct Token
+{
+    text: String,
+    line: usize,
+}
+
+struct Macro
+{
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+enum Fixup
+{
+    Address(usize), // low 12 bits of the instruction at this address
+    Pointer(usize), // 16 bits at this address
+    Unpack(usize),  // the two byte immediates of an :unpack at this address
+}
+
+struct Assembler
+{
+    tokens: VecDeque<Token>,
+    line: usize, // line of the last token taken, for errors
+    memory: Vec<u8>,
+    pc: usize,
+    end: usize,
+    has_main: bool,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<(Fixup, String, usize)>, // what to patch, the label it needs, line
+    branches: Vec<usize>,                // jumps of open if ... begin / else blocks
+    loops: Vec<(usize, Vec<usize>)>,     // start of open loops and the jumps of their whiles
+}
+
+fn tokenize(source: &str) -> VecDeque<Token>
+{
+    source.lines().enumerate().flat_map(|(number, line)|
+    {
+        let code = line.split('#').next().unwrap_or_default();
+        code.split_whitespace().map(move |text| Token { text: text.to_string(), line: number + 1 })
+    }).collect()
+}
+
+fn parse_number(text: &str) -> Option<f64>
+{
+    let (negative, digits) = match text.strip_prefix('-')
+    {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x")
+    {
+        i64::from_str_radix(hex, 16).ok()?
+    }
+    else if let Some(binary) = digits.strip_prefix("0b")
+    {
+        i64::from_str_radix(binary, 2).ok()?
+    }
+    else
+    {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value as f64 } else { value as f64 })
+}
+
+fn register_number(text: &str) -> Option<u8>
+{
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next())
+    {
+        (Some('v'), Some(c), None) | (Some('V'), Some(c), None) => c.to_digit(16).map(|n| n as u8),
+        _ => None,
+    }
+}
+
+impl Assembler
+{
+    fn error<T>(&self, message: &str) -> Result<T, String>
+    {
+        Err(format!("line {}: {}", self.line, message))
+    }
+
+    fn next(& mut self) -> Result<String, String>
+    {
+        match self.tokens.pop_front()
+        {
+            Some(token) =>
+            {
+                self.line = token.line;
+                Ok(token.text)
+            },
+            None => self.error("unexpected end of the program"),
+        }
+    }
+
+    fn peek(&self) -> Option<&str>
+    {
+        self.tokens.front().map(|t| t.text.as_str())
+    }
+
+    fn expect(& mut self, text: &str) -> Result<(), String>
+    {
+        match self.next()?
+        {
+            ref t if t == text => Ok(()),
+            t => self.error(&format!("expected '{}', got '{}'", text, t)),
+        }
+    }
+
+    fn emit(& mut self, byte: u8) -> Result<(), String>
+    {
+        if self.pc >= self.memory.len()
+        {
+            return self.error("the program doesn't fit in memory");
+        }
+        self.memory[self.pc] = byte;
+        self.pc += 1;
+        self.end = self.end.max(self.pc);
+        Ok(())
+    }
+
+    fn instruction(& mut self, opcode: u16) -> Result<(), String>
+    {
+        self.emit((opcode >> 8) as u8)?;
+        self.emit(opcode as u8)
+    }
+
+    fn is_register(&self, text: &str) -> bool
+    {
+        register_number(text).is_some() || self.aliases.contains_key(text)
+    }
+
+    fn register(& mut self) -> Result<u16, String>
+    {
+        let text = self.next()?;
+        match register_number(&text).or_else(|| self.aliases.get(&text).cloned())
+        {
+            Some(n) => Ok(n as u16),
+            None => self.error(&format!("expected a register, got '{}'", text)),
+        }
+    }
+
+    // a number, a constant, a label defined so far or a { :calc expression }
+    fn number(& mut self) -> Result<f64, String>
+    {
+        let text = self.next()?;
+        if text == "{"
+        {
+            return self.calc();
+        }
+        match self.known_value(&text)
+        {
+            Some(value) => Ok(value),
+            None => self.error(&format!("expected a number, got '{}'", text)),
+        }
+    }
+
+    fn known_value(&self, text: &str) -> Option<f64>
+    {
+        parse_number(text)
+            .or_else(|| self.constants.get(text).cloned())
+            .or_else(|| self.labels.get(text).map(|&a| a as f64))
+    }
+
+    // an 8 bit immediate, negative numbers wrap around
+    fn byte(& mut self) -> Result<u16, String>
+    {
+        match self.number()? as i64
+        {
+            n if (-128..=255).contains(&n) => Ok((n & 0xFF) as u16),
+            n => self.error(&format!("{} doesn't fit in a byte", n)),
+        }
+    }
+
+    // a value of `bits` bits that may be a label defined further down, which
+    // is then patched in at the end
+    fn address(& mut self, fixup: Fixup, bits: u32) -> Result<u16, String>
+    {
+        let text = self.next()?;
+        let value = if text == "{"
+        {
+            self.calc()?
+        }
+        else
+        {
+            match self.known_value(&text)
+            {
+                Some(value) => value,
+                None if !text.starts_with(|c: char| c.is_ascii_digit() || c == '-') =>
+                {
+                    self.fixups.push((fixup, text, self.line));
+                    return Ok(0);
+                },
+                None => return self.error(&format!("expected an address, got '{}'", text)),
+            }
+        };
+
+        match value as i64
+        {
+            n if n >= 0 && n < 1 << bits => Ok(n as u16),
+            n => self.error(&format!("address {} is out of range", n)),
+        }
+    }
+
+    fn calc(& mut self) -> Result<f64, String>
+    {
+        let mut expression = Vec::new();
+        loop
+        {
+            match self.next()?
+            {
+                ref t if t == "}" => break,
+                t => expression.push(t),
+            }
+        }
+        let (value, used) = self.expression(&expression)?;
+        if used != expression.len()
+        {
+            return self.error(&format!("unexpected '{}' in expression", expression[used]));
+        }
+        Ok(value)
+    }
+
+    // evaluates tokens from the start of `tokens`, returns the value and how
+    // many tokens it took
+    fn expression(&self, tokens: &[String]) -> Result<(f64, usize), String>
+    {
+        let (left, used) = self.term(tokens)?;
+        let op = match tokens.get(used)
+        {
+            Some(op) if op != ")" => op.as_str(),
+            _ => return Ok((left, used)),
+        };
+        let (right, rest) = self.expression(&tokens[used + 1..])?;
+        let value = match op
+        {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (left as i64 & right as i64) as f64,
+            "|" => (left as i64 | right as i64) as f64,
+            "^" => (left as i64 ^ right as i64) as f64,
+            "<<" => ((left as i64) << (right as i64)) as f64,
+            ">>" => ((left as i64) >> (right as i64)) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => return self.error(&format!("unknown operator '{}'", op)),
+        };
+        Ok((value, used + 1 + rest))
+    }
+
+    fn term(&self, tokens: &[String]) -> Result<(f64, usize), String>
+    {
+        let token = match tokens.first()
+        {
+            Some(token) => token.as_str(),
+            None => return self.error("incomplete expression"),
+        };
+
+        let unary: Option<fn(f64) -> f64> = match token
+        {
+            "-" => Some(|x| -x),
+            "~" => Some(|x| !(x as i64) as f64),
+            "!" => Some(|x| (x == 0.0) as i64 as f64),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(f) = unary
+        {
+            let (value, used) = self.term(&tokens[1..])?;
+            return Ok((f(value), used + 1));
+        }
+
+        match token
+        {
+            "(" =>
+            {
+                let (value, used) = self.expression(&tokens[1..])?;
+                match tokens.get(used + 1).map(|t| t.as_str())
+                {
+                    Some(")") => Ok((value, used + 2)),
+                    _ => self.error("missing ')'"),
+                }
+            },
+            "@" =>
+            {
+                let (address, used) = self.term(&tokens[1..])?;
+                Ok((self.memory.get(address as usize).cloned().unwrap_or(0) as f64, used + 1))
+            },
+            "PI" => Ok((consts::PI, 1)),
+            "E" => Ok((consts::E, 1)),
+            "HERE" => Ok((self.pc as f64, 1)),
+            _ => match self.known_value(token)
+            {
+                Some(value) => Ok((value, 1)),
+                None => self.error(&format!("unknown name '{}' in expression", token)),
+            },
+        }
+    }
+
+    // the skip instruction that makes the next instruction run only when the
+    // condition holds. < > <= >= subtract in a temporary register, VF unless
+    // compare-temp is aliased to another, and test the borrow that leaves in
+    // VF, as Octo does.
+    fn condition(& mut self, negated: bool) -> Result<(), String>
+    {
+        let x = self.register()?;
+        let mut op = self.next()?;
+        if negated
+        {
+            op = match op.as_str()
+            {
+                "==" => "!=",
+                "!=" => "==",
+                "key" => "-key",
+                "-key" => "key",
+                "<" => ">=",
+                ">" => "<=",
+                "<=" => ">",
+                ">=" => "<",
+                other => return self.error(&format!("unknown comparison '{}'", other)),
+            }.to_string();
+        }
+
+        let temp = self.aliases.get("compare-temp").cloned().unwrap_or(0xF) as u16;
+        match op.as_str()
+        {
+            "key" => return self.instruction(0xE0A1 | x << 8),
+            "-key" => return self.instruction(0xE09E | x << 8),
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {},
+            other => return self.error(&format!("unknown comparison '{}'", other)),
+        }
+
+        let rhs_is_register = self.peek().is_some_and(|t| self.is_register(t));
+        match op.as_str()
+        {
+            "==" if rhs_is_register => { let y = self.register()?; self.instruction(0x9000 | x << 8 | y << 4) },
+            "==" => { let n = self.byte()?; self.instruction(0x4000 | x << 8 | n) },
+            "!=" if rhs_is_register => { let y = self.register()?; self.instruction(0x5000 | x << 8 | y << 4) },
+            "!=" => { let n = self.byte()?; self.instruction(0x3000 | x << 8 | n) },
+            _ =>
+            {
+                if rhs_is_register
+                {
+                    let y = self.register()?;
+                    self.instruction(0x8000 | temp << 8 | y << 4)?;
+                }
+                else
+                {
+                    let n = self.byte()?;
+                    self.instruction(0x6000 | temp << 8 | n)?;
+                }
+                let (subtract, skip) = match op.as_str()
+                {
+                    ">" => (0x5, 0x3F01),
+                    "<" => (0x7, 0x3F01),
+                    ">=" => (0x7, 0x4F01),
+                    _ => (0x5, 0x4F01),
+                };
+                self.instruction(0x8000 | temp << 8 | x << 4 | subtract)?;
+                self.instruction(skip)
+            },
+        }
+    }
+
+    // a jump whose target is filled in later, returns where it is
+    fn placeholder_jump(& mut self) -> Result<usize, String>
+    {
+        let address = self.pc;
+        self.instruction(0x1000)?;
+        Ok(address)
+    }
+
+    fn patch_jump(& mut self, at: usize, target: usize)
+    {
+        self.memory[at] = 0x10 | (target >> 8) as u8 & 0xF;
+        self.memory[at + 1] = target as u8;
+    }
+
+    fn define_label(& mut self, name: String, address: usize) -> Result<(), String>
+    {
+        if self.labels.contains_key(&name) || self.constants.contains_key(&name)
+        {
+            return self.error(&format!("'{}' is already defined", name));
+        }
+        self.labels.insert(name, address);
+        Ok(())
+    }
+
+    fn statement(& mut self) -> Result<(), String>
+    {
+        let token = self.next()?;
+        match token.as_str()
+        {
+            ":" =>
+            {
+                let name = self.next()?;
+                // a program that starts with main doesn't need the jump to it
+                if name == "main" && self.has_main && self.pc == START + 2
+                {
+                    self.has_main = false;
+                    self.pc = START;
+                    self.end = START;
+                }
+                let pc = self.pc;
+                self.define_label(name, pc)
+            },
+            ":next" =>
+            {
+                let name = self.next()?;
+                let pc = self.pc + 1;
+                self.define_label(name, pc)
+            },
+            ":const" =>
+            {
+                let name = self.next()?;
+                let value = self.number()?;
+                self.constants.insert(name, value);
+                Ok(())
+            },
+            ":calc" =>
+            {
+                let name = self.next()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+                Ok(())
+            },
+            ":alias" =>
+            {
+                let name = self.next()?;
+                let register = self.register()? as u8;
+                self.aliases.insert(name, register);
+                Ok(())
+            },
+            ":org" =>
+            {
+                let address = self.number()? as i64;
+                if address < 0 || address as usize >= self.memory.len()
+                {
+                    return self.error(&format!(":org {} is outside of memory", address));
+                }
+                // the rom is what's from 0x200 on, anything below would be lost
+                if (address as usize) < START
+                {
+                    return self.error(&format!(":org 0x{:X} is below 0x{:X}, where programs start", address, START));
+                }
+                self.pc = address as usize;
+                Ok(())
+            },
+            ":byte" =>
+            {
+                let byte = self.byte()?;
+                self.emit(byte as u8)
+            },
+            ":pointer" =>
+            {
+                let address = self.address(Fixup::Pointer(self.pc), 16)?;
+                self.instruction(address)
+            },
+            ":call" =>
+            {
+                let address = self.address(Fixup::Address(self.pc), 12)?;
+                self.instruction(0x2000 | address)
+            },
+            ":unpack" =>
+            {
+                // hi := nibble << 4 | address >> 8, lo := address & 0xFF
+                let nibble = match self.peek()
+                {
+                    Some("long") => { self.next()?; 0 },
+                    _ => self.number()? as u16 & 0xF,
+                };
+                let address = self.address(Fixup::Unpack(self.pc), 16)?;
+                let high = self.aliases.get("unpack-hi").cloned().unwrap_or(0) as u16;
+                let low = self.aliases.get("unpack-lo").cloned().unwrap_or(1) as u16;
+                self.instruction(0x6000 | high << 8 | nibble << 4 | address >> 8)?;
+                self.instruction(0x6000 | low << 8 | address & 0xFF)
+            },
+            ":macro" =>
+            {
+                let name = self.next()?;
+                let mut args = Vec::new();
+                loop
+                {
+                    match self.next()?
+                    {
+                        ref t if t == "{" => break,
+                        t => args.push(t),
+                    }
+                }
+                let mut body = Vec::new();
+                let mut depth = 1;
+                while depth > 0
+                {
+                    let token = self.tokens.pop_front().ok_or_else(|| format!("line {}: unterminated :macro", self.line))?;
+                    depth += match token.text.as_str() { "{" => 1, "}" => -1, _ => 0 };
+                    if depth > 0
+                    {
+                        body.push(token);
+                    }
+                }
+                self.macros.insert(name, Macro { args, body });
+                Ok(())
+            },
+            ":breakpoint" | ":proto" => self.next().map(|_| ()),
+            ":monitor" => { self.next()?; self.next().map(|_| ()) },
+            ":stringmode" | ":assert" => self.error(&format!("{} is not supported", token)),
+
+            "clear" => self.instruction(0x00E0),
+            "return" | ";" => self.instruction(0x00EE),
+            "exit" => self.instruction(0x00FD),
+            "lores" => self.instruction(0x00FE),
+            "hires" => self.instruction(0x00FF),
+            "scroll-right" => self.instruction(0x00FB),
+            "scroll-left" => self.instruction(0x00FC),
+            "scroll-down" => { let n = self.number()? as u16 & 0xF; self.instruction(0x00C0 | n) },
+            "scroll-up" => { let n = self.number()? as u16 & 0xF; self.instruction(0x00D0 | n) },
+            "audio" => self.instruction(0xF002),
+            "plane" => { let n = self.number()? as u16 & 0xF; self.instruction(0xF001 | n << 8) },
+            "bcd" => { let x = self.register()?; self.instruction(0xF033 | x << 8) },
+            "saveflags" => { let x = self.register()?; self.instruction(0xF075 | x << 8) },
+            "loadflags" => { let x = self.register()?; self.instruction(0xF085 | x << 8) },
+            "save" | "load" =>
+            {
+                let x = self.register()?;
+                if self.peek() == Some("-")
+                {
+                    self.next()?;
+                    let y = self.register()?;
+                    self.instruction(if token == "save" { 0x5002 } else { 0x5003 } | x << 8 | y << 4)
+                }
+                else
+                {
+                    self.instruction(if token == "save" { 0xF055 } else { 0xF065 } | x << 8)
+                }
+            },
+            "sprite" =>
+            {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.number()? as u16 & 0xF;
+                self.instruction(0xD000 | x << 8 | y << 4 | n)
+            },
+            "jump" => { let a = self.address(Fixup::Address(self.pc), 12)?; self.instruction(0x1000 | a) },
+            "jump0" => { let a = self.address(Fixup::Address(self.pc), 12)?; self.instruction(0xB000 | a) },
+            "native" => { let a = self.address(Fixup::Address(self.pc), 12)?; self.instruction(a) },
+
+            "delay" | "buzzer" | "pitch" =>
+            {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let low = match token.as_str() { "delay" => 0x15, "buzzer" => 0x18, _ => 0x3A };
+                self.instruction(0xF000 | x << 8 | low)
+            },
+
+            "if" =>
+            {
+                let block = self.tokens.iter().map(|t| t.text.as_str()).find(|&t| t == "then" || t == "begin") == Some("begin");
+                if block
+                {
+                    self.condition(true)?;
+                    self.expect("begin")?;
+                    let jump = self.placeholder_jump()?;
+                    self.branches.push(jump);
+                    Ok(())
+                }
+                else
+                {
+                    self.condition(false)?;
+                    self.expect("then")
+                }
+            },
+            "else" =>
+            {
+                let begin = match self.branches.pop()
+                {
+                    Some(begin) => begin,
+                    None => return self.error("else without if ... begin"),
+                };
+                let jump = self.placeholder_jump()?;
+                let pc = self.pc;
+                self.patch_jump(begin, pc);
+                self.branches.push(jump);
+                Ok(())
+            },
+            "end" =>
+            {
+                match self.branches.pop()
+                {
+                    Some(jump) => { let pc = self.pc; self.patch_jump(jump, pc); Ok(()) },
+                    None => self.error("end without if ... begin"),
+                }
+            },
+            "loop" =>
+            {
+                self.loops.push((self.pc, Vec::new()));
+                Ok(())
+            },
+            "while" =>
+            {
+                if self.loops.is_empty()
+                {
+                    return self.error("while outside of a loop");
+                }
+                self.condition(true)?;
+                let jump = self.placeholder_jump()?;
+                self.loops.last_mut().unwrap().1.push(jump);
+                Ok(())
+            },
+            "again" =>
+            {
+                let (start, whiles) = match self.loops.pop()
+                {
+                    Some(open) => open,
+                    None => return self.error("again without loop"),
+                };
+                self.instruction(0x1000 | start as u16 & 0xFFF)?;
+                let pc = self.pc;
+                for jump in whiles
+                {
+                    self.patch_jump(jump, pc);
+                }
+                Ok(())
+            },
+
+            "i" =>
+            {
+                match self.next()?.as_str()
+                {
+                    ":=" => match self.peek()
+                    {
+                        Some("hex") => { self.next()?; let x = self.register()?; self.instruction(0xF029 | x << 8) },
+                        Some("bighex") => { self.next()?; let x = self.register()?; self.instruction(0xF030 | x << 8) },
+                        Some("long") =>
+                        {
+                            self.next()?;
+                            self.instruction(0xF000)?;
+                            let address = self.address(Fixup::Pointer(self.pc), 16)?;
+                            self.instruction(address)
+                        },
+                        _ => { let a = self.address(Fixup::Address(self.pc), 12)?; self.instruction(0xA000 | a) },
+                    },
+                    "+=" => { let x = self.register()?; self.instruction(0xF01E | x << 8) },
+                    op => self.error(&format!("unknown operator '{}' for i", op)),
+                }
+            },
+
+            // a bare number is a byte of data
+            _ if parse_number(&token).is_some() =>
+            {
+                self.tokens.push_front(Token { text: token, line: self.line });
+                let byte = self.byte()?;
+                self.emit(byte as u8)
+            },
+
+            _ if self.is_register(&token) =>
+            {
+                self.tokens.push_front(Token { text: token, line: self.line });
+                self.register_statement()
+            },
+
+            _ if self.macros.contains_key(&token) =>
+            {
+                let (args, body) = match self.macros.get(&token)
+                {
+                    Some(m) => (m.args.clone(), m.body.clone()),
+                    None => unreachable!(),
+                };
+                let mut values = HashMap::new();
+                for arg in args
+                {
+                    let value = self.next()?;
+                    values.insert(arg, value);
+                }
+                for mut t in body.into_iter().rev()
+                {
+                    if let Some(value) = values.get(&t.text)
+                    {
+                        t.text = value.clone();
+                    }
+                    self.tokens.push_front(t);
+                }
+                Ok(())
+            },
+
+            // anything else is a subroutine call
+            _ =>
+            {
+                self.tokens.push_front(Token { text: token, line: self.line });
+                let address = self.address(Fixup::Address(self.pc), 12)?;
+                self.instruction(0x2000 | address)
+            },
+        }
+    }
+
+    fn register_statement(& mut self) -> Result<(), String>
+    {
+        let x = self.register()?;
+        let op = self.next()?;
+        let rhs_is_register = self.peek().is_some_and(|t| self.is_register(t));
+
+        let alu = match op.as_str()
+        {
+            ":=" => 0x0,
+            "|=" => 0x1,
+            "&=" => 0x2,
+            "^=" => 0x3,
+            "+=" => 0x4,
+            "-=" => 0x5,
+            ">>=" => 0x6,
+            "=-" => 0x7,
+            "<<=" => 0xE,
+            _ => return self.error(&format!("unknown operator '{}'", op)),
+        };
+        if rhs_is_register
+        {
+            let y = self.register()?;
+            return self.instruction(0x8000 | x << 8 | y << 4 | alu);
+        }
+
+        match op.as_str()
+        {
+            ":=" => match self.peek()
+            {
+                Some("delay") => { self.next()?; self.instruction(0xF007 | x << 8) },
+                Some("key") => { self.next()?; self.instruction(0xF00A | x << 8) },
+                Some("random") => { self.next()?; let n = self.byte()?; self.instruction(0xC000 | x << 8 | n) },
+                _ => { let n = self.byte()?; self.instruction(0x6000 | x << 8 | n) },
+            },
+            "+=" => { let n = self.byte()?; self.instruction(0x7000 | x << 8 | n) },
+            "-=" => { let n = self.byte()?; self.instruction(0x7000 | x << 8 | (0x100 - n) & 0xFF) },
+            _ => self.error(&format!("'{}' needs a register on the right", op)),
+        }
+    }
+
+    fn resolve(& mut self) -> Result<(), String>
+    {
+        for (fixup, name, line) in std::mem::take(&mut self.fixups)
+        {
+            let address = match self.labels.get(&name).cloned().or_else(|| self.constants.get(&name).map(|&c| c as usize))
+            {
+                Some(address) => address,
+                None => return Err(format!("line {}: undefined name '{}'", line, name)),
+            };
+            match fixup
+            {
+                Fixup::Address(at) =>
+                {
+                    if address > 0xFFF
+                    {
+                        return Err(format!("line {}: '{}' is above 0xFFF, use long", line, name));
+                    }
+                    self.memory[at] |= (address >> 8) as u8;
+                    self.memory[at + 1] = address as u8;
+                },
+                Fixup::Pointer(at) =>
+                {
+                    self.memory[at] = (address >> 8) as u8;
+                    self.memory[at + 1] = address as u8;
+                },
+                Fixup::Unpack(at) =>
+                {
+                    self.memory[at + 1] |= (address >> 8) as u8;
+                    self.memory[at + 3] = address as u8;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, String>
+{
+    let mut assembler = Assembler
+    {
+        tokens: tokenize(source),
+        line: 1,
+        memory: vec![0; 0x10000],
+        pc: START,
+        end: START,
+        has_main: true,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        branches: Vec::new(),
+        loops: Vec::new(),
+    };
+
+    // room for a jump to main, dropped again if the program starts with it
+    assembler.instruction(0x1000)?;
+    while !assembler.tokens.is_empty()
+    {
+        assembler.statement()?;
+    }
+
+    if !assembler.branches.is_empty()
+    {
+        return assembler.error("if ... begin without end");
+    }
+    if !assembler.loops.is_empty()
+    {
+        return assembler.error("loop without again");
+    }
+    if assembler.has_main
+    {
+        match assembler.labels.get("main").cloned()
+        {
+            Some(main) => assembler.patch_jump(START, main),
+            None => return Err("the program has no main label".to_string()),
+        }
+    }
+    assembler.resolve()?;
+
+    Ok(assembler.memory[START..assembler.end.max(START)].to_vec())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::assemble;
+
+    fn bytes(source: &str) -> Vec<u8>
+    {
+        assemble(source).unwrap()
+    }
+
+    #[test]
+    fn main_first_needs_no_jump()
+    {
+        assert_eq!(bytes(": main v0 := 5 v1 += 2 v2 -= 1 clear ;"), [0x60, 0x05, 0x71, 0x02, 0x72, 0xFF, 0x00, 0xE0, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn jump_to_main_and_calls()
+    {
+        assert_eq!(bytes(": draw ; : main draw jump main"), [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02, 0x12, 0x04]);
+    }
+
+    #[test]
+    fn labels_further_down()
+    {
+        assert_eq!(bytes(": main i := data sprite v0 v1 1 : data 0xFF 0b1010"), [0xA2, 0x04, 0xD0, 0x11, 0xFF, 0x0A]);
+    }
+
+    #[test]
+    fn comparisons()
+    {
+        assert_eq!(bytes(": main if v1 == 3 then v0 := 1 if v1 != v2 then v0 := 2"),
+            [0x41, 0x03, 0x60, 0x01, 0x51, 0x20, 0x60, 0x02]);
+        assert_eq!(bytes(": main if v1 key then clear"), [0xE1, 0xA1, 0x00, 0xE0]);
+
+        // vF := 5, vF =- v1, skip if there was no borrow
+        assert_eq!(bytes(": main if v1 < 5 then clear"), [0x6F, 0x05, 0x8F, 0x17, 0x3F, 0x01, 0x00, 0xE0]);
+    }
+
+    #[test]
+    fn compare_temp_alias_still_tests_vf()
+    {
+        // v3 := v2, v3 =- v1, skip if there was a borrow
+        assert_eq!(bytes(":alias compare-temp v3 : main if v1 >= v2 then clear"),
+            [0x83, 0x20, 0x83, 0x17, 0x4F, 0x01, 0x00, 0xE0]);
+    }
+
+    #[test]
+    fn blocks_and_loops()
+    {
+        // 200: skip unless v0 == 1, 202: jump to else, 204: clear, 206: jump to end, 208: return
+        assert_eq!(bytes(": main if v0 == 1 begin clear else ; end"),
+            [0x30, 0x01, 0x12, 0x08, 0x00, 0xE0, 0x12, 0x0A, 0x00, 0xEE]);
+        // 200: v0 += 1, 202: skip if v0 != 8, 204: jump out, 206: jump back
+        assert_eq!(bytes(": main loop v0 += 1 while v0 != 8 again"), [0x70, 0x01, 0x40, 0x08, 0x12, 0x08, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn constants_calc_and_macros()
+    {
+        // every operator has the same precedence and groups from the right
+        assert_eq!(bytes(":calc size { 2 * 3 + 1 } :const speed 3 : main v0 := size v1 := speed"),
+            [0x60, 0x08, 0x61, 0x03]);
+        assert_eq!(bytes(":macro twice r { r += r r += r } : main twice v4"), [0x84, 0x44, 0x84, 0x44]);
+    }
+
+    #[test]
+    fn unpack_and_org()
+    {
+        assert_eq!(bytes(": main :unpack 0xA data : data 0x00"), [0x60, 0xA2, 0x61, 0x04, 0x00]);
+        assert_eq!(bytes(": main clear :org 0x206 ;"), [0x00, 0xE0, 0x00, 0x00, 0x00, 0x00, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn errors()
+    {
+        assert!(assemble(": main :org 0x100 0xFF").unwrap_err().contains("below 0x200"));
+        assert!(assemble(": main jump nowhere").unwrap_err().contains("undefined name 'nowhere'"));
+        assert!(assemble("v0 := 1").unwrap_err().contains("no main"));
+        assert!(assemble(": main if v0 == 1 begin clear").is_err());
+    }
+}
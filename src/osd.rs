@@ -0,0 +1,180 @@
+// Text drawn over the window: the frame rate and speed (F3 shows and hides
+// them) and the state of the VM in the top left, and short notices such as
+// "reloaded" or a load error in the bottom left. It goes on top of the
+// emulated screen after it has been scaled, and never into the VM's
+// framebuffer, so screenshots and recordings don't see it.
+
+use std::time::{Duration, Instant};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use crate::font;
+use crate::palette::Palette;
+use crate::scale::Viewport;
+
+const NOTICE_TIME: Duration = Duration::from_secs(2);
+const ERROR_TIME: Duration = Duration::from_secs(6);
+const TEXT_WIDTH: u32 = 384; // text is scaled like a 384 pixel wide screen would be
+const METER_TIME: Duration = Duration::from_secs(1);
+
+// counts frames and instructions and turns them into rates once a second
+struct Meter
+{
+    since: Instant,
+    frames: u32,       // frames of the window
+    emulated: u32,     // frames of the VM, which differ while paused or fast forwarding
+    instructions: u64,
+    text: String,
+}
+
+impl Meter
+{
+    // true when the rates changed
+    fn count(& mut self, emulated: u32, instructions: u64) -> bool
+    {
+        self.frames += 1;
+        self.emulated += emulated;
+        self.instructions += instructions;
+
+        let elapsed = self.since.elapsed();
+        if elapsed < METER_TIME
+        {
+            return false;
+        }
+        let seconds = elapsed.as_secs_f64();
+        self.text = format!("{:.0} fps  {:.0} ips  {:.2}x", self.frames as f64 / seconds,
+            self.instructions as f64 / seconds, self.emulated as f64 / seconds / 60.0);
+        self.since = Instant::now();
+        self.frames = 0;
+        self.emulated = 0;
+        self.instructions = 0;
+        true
+    }
+}
+
+pub struct Osd
+{
+    notices: Vec<(String, Instant)>, // text and when it goes away
+    status: Vec<String>,             // lines that stay up, such as "paused"
+    meter: Meter,
+    show_meter: bool,
+    dirty: bool,
+}
+
+impl Osd
+{
+    pub fn new() -> Osd
+    {
+        Osd
+        {
+            notices: Vec::new(),
+            status: Vec::new(),
+            meter: Meter
+            {
+                since: Instant::now(),
+                frames: 0,
+                emulated: 0,
+                instructions: 0,
+                text: "-- fps".to_string(),
+            },
+            show_meter: false,
+            dirty: false,
+        }
+    }
+
+    pub fn toggle_meter(& mut self)
+    {
+        self.show_meter = !self.show_meter;
+        self.dirty = true;
+    }
+
+    // called once per window frame with what the VM ran in it
+    pub fn count_frame(& mut self, emulated: u32, instructions: u64)
+    {
+        if self.meter.count(emulated, instructions) && self.show_meter
+        {
+            self.dirty = true;
+        }
+    }
+
+    // replaces the lines that stay up until the state they describe changes
+    pub fn set_status(& mut self, status: Vec<String>)
+    {
+        if status != self.status
+        {
+            self.status = status;
+            self.dirty = true;
+        }
+    }
+
+    pub fn notify(& mut self, text: &str)
+    {
+        self.notices.push((text.to_string(), Instant::now() + NOTICE_TIME));
+        self.dirty = true;
+    }
+
+    // errors stay up longer, and go to stderr as well
+    pub fn error(& mut self, text: &str)
+    {
+        eprintln!("{}", text);
+        self.notices.push((text.to_string(), Instant::now() + ERROR_TIME));
+        self.dirty = true;
+    }
+
+    // drops the notices that are done, true if the window has to be redrawn
+    // because something appeared or went away
+    pub fn update(& mut self) -> bool
+    {
+        let now = Instant::now();
+        let count = self.notices.len();
+        self.notices.retain(|&(_, until)| until > now);
+        let dirty = self.dirty || self.notices.len() != count;
+        self.dirty = false;
+        dirty
+    }
+
+    // the meter and status lines top down in the upper left of the viewport,
+    // the notices with the newest at the bottom in the lower left
+    pub fn draw(&self, canvas: &mut Canvas<Window>, viewport: &Viewport, palette: &Palette)
+    {
+        let scale = (viewport.width / TEXT_WIDTH).max(1) as i32;
+        let line_height = font::CELL_HEIGHT as i32 * scale;
+        let x = viewport.x + scale * 2;
+
+        let meter = if self.show_meter { Some(&self.meter.text) } else { None };
+        let top = viewport.y + scale * 2;
+        for (i, text) in meter.into_iter().chain(self.status.iter()).enumerate()
+        {
+            draw_line(canvas, text, x, top + i as i32 * line_height, scale, palette);
+        }
+
+        let bottom = viewport.y + viewport.height as i32 - scale * 2;
+        for (i, (text, _)) in self.notices.iter().rev().enumerate()
+        {
+            draw_line(canvas, text, x, bottom - (i as i32 + 1) * line_height, scale, palette);
+        }
+    }
+}
+
+// a line of text at (x, y) on a translucent box, so it can be read over the game
+fn draw_line(canvas: &mut Canvas<Window>, text: &str, x: i32, y: i32, scale: i32, palette: &Palette)
+{
+    let line_height = font::CELL_HEIGHT as i32 * scale;
+
+    canvas.set_blend_mode(BlendMode::Blend);
+    let [r, g, b] = palette.background;
+    canvas.set_draw_color(Color::RGBA(r, g, b, 192));
+    let width = (font::text_width(text) as i32 + 2) * scale;
+    let _ = canvas.fill_rect(Rect::new(x - scale, y - scale, width as u32, line_height as u32));
+    canvas.set_blend_mode(BlendMode::None);
+
+    let [r, g, b] = palette.foreground;
+    canvas.set_draw_color(Color::RGB(r, g, b));
+    for (px, py) in font::pixels(text)
+    {
+        let _ = canvas.fill_rect(Rect::new(x + px as i32 * scale, y + py as i32 * scale, scale as u32, scale as u32));
+    }
+}
@@ -0,0 +1,103 @@
+// Colors used to turn the 1-bit `gfx` buffer into RGB pixels. Everything that
+// shows or saves the screen goes through the same palette so that a screenshot
+// looks exactly like the window.
+
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Palette
+{
+    pub background: [u8; 3],
+    pub foreground: [u8; 3],
+}
+
+impl Default for Palette
+{
+    fn default() -> Palette
+    {
+        Palette
+        {
+            background: [0x00, 0x00, 0x00],
+            foreground: [0xFF, 0xFF, 0xFF],
+        }
+    }
+}
+
+// the "RRGGBB,RRGGBB" form that Palette::parse reads
+impl fmt::Display for Palette
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        let [r, g, b] = self.background;
+        let [fr, fg, fb] = self.foreground;
+        write!(f, "{:02X}{:02X}{:02X},{:02X}{:02X}{:02X}", r, g, b, fr, fg, fb)
+    }
+}
+
+impl Palette
+{
+    pub const PRESETS: [&'static str; 5] = ["mono", "green", "amber", "lcd", "inverted"];
+
+    // either a preset name or two colors as "RRGGBB,RRGGBB" (background first)
+    pub fn parse(text: &str) -> Option<Palette>
+    {
+        let (background, foreground) = match text
+        {
+            "mono" => ([0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF]),
+            "green" => ([0x00, 0x1A, 0x00], [0x33, 0xFF, 0x33]),
+            "amber" => ([0x1A, 0x0F, 0x00], [0xFF, 0xB0, 0x00]),
+            "lcd" => ([0x9B, 0xBC, 0x0F], [0x0F, 0x38, 0x0F]),
+            "inverted" => ([0xFF, 0xFF, 0xFF], [0x00, 0x00, 0x00]),
+            _ =>
+            {
+                let mut colors = text.split(',').map(parse_color);
+                let colors = (colors.next()??, colors.next()??, colors.next());
+                match colors
+                {
+                    (background, foreground, None) => (background, foreground),
+                    _ => return None,
+                }
+            },
+        };
+        Some(Palette { background, foreground })
+    }
+
+    pub fn color(&self, pixel: u8) -> [u8; 3]
+    {
+        if pixel != 0
+        {
+            self.foreground
+        }
+        else
+        {
+            self.background
+        }
+    }
+
+    // renders a `width` x `height` framebuffer as packed RGB24, every pixel
+    // blown up to a `scale` x `scale` block
+    pub fn render(&self, gfx: &[u8], width: usize, height: usize, scale: usize) -> Vec<u8>
+    {
+        let mut buffer = Vec::with_capacity(width * height * scale * scale * 3);
+        for y in 0..height * scale
+        {
+            for x in 0..width * scale
+            {
+                buffer.extend_from_slice(&self.color(gfx[(y / scale) * width + x / scale]));
+            }
+        }
+        buffer
+    }
+}
+
+// "RRGGBB", with or without a leading '#'
+fn parse_color(text: &str) -> Option<[u8; 3]>
+{
+    let text = text.trim().trim_start_matches('#');
+    if text.len() != 6
+    {
+        return None;
+    }
+    let value = u32::from_str_radix(text, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
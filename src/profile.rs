@@ -0,0 +1,238 @@
+// The profiler for --profile: counts the instructions run by kind and by
+// address, and charges each one to the subroutine it ran in. Subroutines are
+// followed through CALL (2NNN) and RET (00EE) into a call tree, whose root is
+// the code reached without a call. The report lists it all from the most run
+// down; --profile-folded writes the call tree as folded stacks, a line per
+// stack with the instructions run in it, for flamegraph.pl or inferno.
+//
+// Instructions are counted, not time: that's what a slow interpreter spends.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::cpu::VM;
+use crate::disasm;
+
+const MEMORY_SIZE: usize = 4096;
+const HOT_ADDRESSES: usize = 20;
+
+// a subroutine as reached through one particular chain of calls
+struct Node
+{
+    address: u16,
+    children: HashMap<u16, usize>, // called subroutine to its node
+    cycles: u64,                   // instructions run in it, not in what it called
+    calls: u64,
+}
+
+pub struct Profile
+{
+    total: u64,
+    patterns: HashMap<&'static str, u64>,
+    addresses: Vec<u64>,
+    opcodes: Vec<u16>, // last opcode run at each address, for the report
+
+    nodes: Vec<Node>,  // the call tree, the root first
+    stack: Vec<usize>, // nodes of the subroutines being run, the root first
+}
+
+// a subroutine wherever it was called from
+struct Subroutine
+{
+    cycles: u64,    // instructions run in it
+    inclusive: u64, // and in what it called
+    calls: u64,
+}
+
+fn percent(count: u64, total: u64) -> f64
+{
+    count as f64 * 100.0 / total.max(1) as f64
+}
+
+fn node_name(address: u16, load_address: u16) -> String
+{
+    if address == load_address { "start".to_string() } else { format!("sub_{:03X}", address) }
+}
+
+impl Profile
+{
+    pub fn new(load_address: u16) -> Profile
+    {
+        Profile
+        {
+            total: 0,
+            patterns: HashMap::new(),
+            addresses: vec![0; MEMORY_SIZE],
+            opcodes: vec![0; MEMORY_SIZE],
+            nodes: vec![Node { address: load_address, children: HashMap::new(), cycles: 0, calls: 1 }],
+            stack: vec![0],
+        }
+    }
+
+    // called before every instruction
+    pub fn record(& mut self, vm: &VM)
+    {
+        let registers = vm.registers();
+        let memory = vm.memory();
+        let pc = registers.pc as usize;
+        if pc + 1 >= memory.len()
+        {
+            return;
+        }
+        let opcode = (memory[pc] as u16) << 8 | memory[pc + 1] as u16;
+
+        // returns, and resets, are followed by going by the VM's stack pointer
+        self.stack.truncate(registers.sp as usize + 1);
+
+        self.total += 1;
+        *self.patterns.entry(disasm::pattern(opcode)).or_insert(0) += 1;
+        self.addresses[pc] += 1;
+        self.opcodes[pc] = opcode;
+        let current = *self.stack.last().unwrap();
+        self.nodes[current].cycles += 1;
+
+        // a call goes on the stack now, it's taken unless the stack overflows
+        if opcode & 0xF000 == 0x2000
+        {
+            let target = opcode & 0x0FFF;
+            let next = self.nodes.len();
+            let child = *self.nodes[current].children.entry(target).or_insert(next);
+            if child == next
+            {
+                self.nodes.push(Node { address: target, children: HashMap::new(), cycles: 0, calls: 0 });
+            }
+            self.nodes[child].calls += 1;
+            self.stack.push(child);
+        }
+    }
+
+    fn inclusive(&self, node: usize) -> u64
+    {
+        self.nodes[node].cycles + self.nodes[node].children.values().map(|&child| self.inclusive(child)).sum::<u64>()
+    }
+
+    // the call tree summed up by subroutine. A recursive one only gets the
+    // instructions under its outermost call in its inclusive count.
+    fn subroutines(&self) -> HashMap<u16, Subroutine>
+    {
+        let mut subroutines = HashMap::new();
+        let mut pending = vec![(0, Vec::new())];
+        while let Some((node, callers)) = pending.pop()
+        {
+            let address = self.nodes[node].address;
+            let recursive = callers.contains(&address);
+            let subroutine = subroutines.entry(address).or_insert(Subroutine { cycles: 0, inclusive: 0, calls: 0 });
+            subroutine.cycles += self.nodes[node].cycles;
+            subroutine.calls += self.nodes[node].calls;
+            if !recursive
+            {
+                subroutine.inclusive += self.inclusive(node);
+            }
+
+            let mut callers = callers;
+            callers.push(address);
+            for &child in self.nodes[node].children.values()
+            {
+                pending.push((child, callers.clone()));
+            }
+        }
+        subroutines
+    }
+
+    fn write_report(&self, out: &mut dyn Write) -> io::Result<()>
+    {
+        let root = self.nodes[0].address;
+        writeln!(out, "instructions: {}", self.total)?;
+
+        writeln!(out, "\nby instruction:")?;
+        let mut patterns: Vec<_> = self.patterns.iter().collect();
+        patterns.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (pattern, &count) in patterns
+        {
+            writeln!(out, "  {:<6} {:>12} {:6.2}%", pattern, count, percent(count, self.total))?;
+        }
+
+        writeln!(out, "\nhot addresses:")?;
+        let mut addresses: Vec<usize> = (0..MEMORY_SIZE).filter(|&a| self.addresses[a] > 0).collect();
+        addresses.sort_by(|&a, &b| self.addresses[b].cmp(&self.addresses[a]).then(a.cmp(&b)));
+        for &address in addresses.iter().take(HOT_ADDRESSES)
+        {
+            let count = self.addresses[address];
+            writeln!(out, "  {:03X}: {:04X}  {:<18} {:>12} {:6.2}%", address, self.opcodes[address],
+                disasm::disassemble(self.opcodes[address]), count, percent(count, self.total))?;
+        }
+
+        writeln!(out, "\nsubroutines:\n  {:<9} {:>7} {:>11} {:>12}", "", "self", "inclusive", "calls")?;
+        let subroutines = self.subroutines();
+        let mut by_cost: Vec<_> = subroutines.iter().collect();
+        by_cost.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(b.0)));
+        for (&address, subroutine) in by_cost
+        {
+            writeln!(out, "  {:<9} {:>6.2}% {:>10.2}% {:>12}", node_name(address, root),
+                percent(subroutine.cycles, self.total), percent(subroutine.inclusive, self.total), subroutine.calls)?;
+        }
+
+        writeln!(out, "\ncall tree:")?;
+        self.write_tree(out, 0, 0)
+    }
+
+    fn write_tree(&self, out: &mut dyn Write, node: usize, depth: usize) -> io::Result<()>
+    {
+        let inclusive = self.inclusive(node);
+        let calls = self.nodes[node].calls;
+        writeln!(out, "  {:width$}{} {:.2}% ({} instructions, {} call{})", "", node_name(self.nodes[node].address,
+            self.nodes[0].address), percent(inclusive, self.total), inclusive, calls, if calls == 1 { "" } else { "s" },
+            width = depth * 2)?;
+
+        let mut children: Vec<usize> = self.nodes[node].children.values().copied().collect();
+        children.sort_by_key(|&child| (u64::MAX - self.inclusive(child), self.nodes[child].address));
+        for child in children
+        {
+            self.write_tree(out, child, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    // "start;sub_2FC;sub_310 1234" for every chain of calls that ran
+    // instructions of its own
+    fn write_folded(&self, out: &mut dyn Write) -> io::Result<()>
+    {
+        let root = self.nodes[0].address;
+        let mut pending = vec![(0, node_name(root, root))];
+        while let Some((node, stack)) = pending.pop()
+        {
+            if self.nodes[node].cycles > 0
+            {
+                writeln!(out, "{} {}", stack, self.nodes[node].cycles)?;
+            }
+            for &child in self.nodes[node].children.values()
+            {
+                pending.push((child, format!("{};{}", stack, node_name(self.nodes[child].address, root))));
+            }
+        }
+        Ok(())
+    }
+
+    // the report, or the folded stacks, to a file or "-" for stdout
+    pub fn save(&self, path: &str, folded: bool) -> io::Result<()>
+    {
+        let mut out: Box<dyn Write> = if path == "-"
+        {
+            Box::new(io::stdout())
+        }
+        else
+        {
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+        if folded
+        {
+            self.write_folded(&mut out)?;
+        }
+        else
+        {
+            self.write_report(&mut out)?;
+        }
+        out.flush()
+    }
+}
@@ -0,0 +1,112 @@
+// Records the framebuffer into an animated GIF. One frame is captured per
+// emulated 60 Hz frame, but a frame is only written once the picture changes:
+// identical frames just make the previous one last longer, so an idle screen
+// costs nothing.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::palette::Palette;
+
+const FRAMES_PER_SECOND: u64 = 60;
+
+pub struct Recorder
+{
+    encoder: gif::Encoder<BufWriter<File>>,
+    width: usize,
+    height: usize,
+    scale: usize,
+
+    pending: Option<Vec<u8>>, // last distinct picture, written once we know how long it lasts
+    frame: u64,               // number of frames captured so far
+    written_centis: u64,      // total delay written to the file, in 1/100 s
+}
+
+impl Recorder
+{
+    pub fn create(path: &Path, width: usize, height: usize, palette: &Palette, scale: usize) -> io::Result<Recorder>
+    {
+        let file = BufWriter::new(File::create(path)?);
+
+        // index 0 is the background, index 1 the foreground, just like gfx
+        let mut global_palette = Vec::with_capacity(6);
+        global_palette.extend_from_slice(&palette.background);
+        global_palette.extend_from_slice(&palette.foreground);
+
+        let mut encoder = gif::Encoder::new(file, (width * scale) as u16, (height * scale) as u16, &global_palette)
+            .map_err(io::Error::other)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(io::Error::other)?;
+
+        Ok(Recorder
+        {
+            encoder,
+            width,
+            height,
+            scale,
+            pending: None,
+            frame: 0,
+            written_centis: 0,
+        })
+    }
+
+    // call once per frame with the current framebuffer
+    pub fn capture(& mut self, gfx: &[u8]) -> io::Result<()>
+    {
+        let mut picture = Vec::with_capacity(self.width * self.height * self.scale * self.scale);
+        for y in 0..self.height * self.scale
+        {
+            for x in 0..self.width * self.scale
+            {
+                picture.push(gfx[(y / self.scale) * self.width + x / self.scale].min(1));
+            }
+        }
+
+        if self.pending.as_ref() != Some(&picture)
+        {
+            self.flush()?;
+            self.pending = Some(picture);
+        }
+
+        self.frame += 1;
+        Ok(())
+    }
+
+    pub fn frames(&self) -> u64
+    {
+        self.frame
+    }
+
+    pub fn finish(mut self) -> io::Result<()>
+    {
+        self.flush()?;
+        self.encoder.into_inner().map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    // writes the pending picture with a delay that covers every frame it was
+    // shown for. GIF delays are in hundredths of a second, so the end time is
+    // rounded and the error is carried into the next frame instead of drifting.
+    fn flush(& mut self) -> io::Result<()>
+    {
+        if let Some(picture) = self.pending.take()
+        {
+            let end_centis = self.frame * 100 / FRAMES_PER_SECOND;
+            let delay = (end_centis - self.written_centis).max(1);
+            self.written_centis += delay;
+
+            let frame = gif::Frame
+            {
+                width: (self.width * self.scale) as u16,
+                height: (self.height * self.scale) as u16,
+                delay: delay.min(u16::MAX as u64) as u16,
+                buffer: Cow::Owned(picture),
+                ..gif::Frame::default()
+            };
+            self.encoder.write_frame(&frame).map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+// Per-rom defaults (title, speed, quirks, keys, colors) looked up by the
+// sha-1 of the rom. The database built into the binary is data/roms.db; a
+// user file in the same format is layered on top of it field by field.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cpu::Quirks;
+use crate::keymap::Keymap;
+use crate::loader::Rom;
+use crate::palette::Palette;
+
+const EMBEDDED: &str = include_str!("../data/roms.db");
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct RomInfo
+{
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub platform: Option<String>,
+    pub cycles_per_frame: Option<u32>,
+    pub quirks: Option<Quirks>,
+    pub keys: Vec<(String, usize)>, // what each keypad key does, in the order listed
+    pub palette: Option<Palette>,
+}
+
+impl RomInfo
+{
+    // fields set in `other` replace ours, the rest are kept
+    pub fn merge(& mut self, other: RomInfo)
+    {
+        self.title = other.title.or_else(|| self.title.take());
+        self.author = other.author.or_else(|| self.author.take());
+        self.platform = other.platform.or_else(|| self.platform.take());
+        self.cycles_per_frame = other.cycles_per_frame.or(self.cycles_per_frame);
+        self.quirks = other.quirks.or(self.quirks);
+        if !other.keys.is_empty()
+        {
+            self.keys = other.keys;
+        }
+        self.palette = other.palette.or(self.palette);
+    }
+
+    // "Tetris by Fran Dachille", or whatever part of it is known
+    pub fn name(&self) -> Option<String>
+    {
+        match (&self.title, &self.author)
+        {
+            (Some(title), Some(author)) => Some(format!("{} by {}", title, author)),
+            (Some(title), None) => Some(title.clone()),
+            _ => None,
+        }
+    }
+
+    // "rotate 4 [q], left 5 [w]", with the keyboard keys of the given keymap
+    pub fn key_hints(&self, keymap: &Keymap) -> String
+    {
+        let hints: Vec<String> = self.keys.iter().map(|&(ref action, key)| match keymap.char_for(key)
+        {
+            Some(c) => format!("{} {:X} [{}]", action, key, c),
+            None => format!("{} {:X}", action, key),
+        }).collect();
+        hints.join(", ")
+    }
+}
+
+pub struct RomDb
+{
+    entries: HashMap<String, RomInfo>,
+}
+
+// $XDG_CONFIG_HOME/rusty-8, or ~/.config/rusty-8
+pub fn config_dir() -> Option<PathBuf>
+{
+    let config = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join("rusty-8"))
+}
+
+pub fn rom_hash(rom: &[u8]) -> String
+{
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+impl RomDb
+{
+    pub fn embedded() -> RomDb
+    {
+        let mut db = RomDb { entries: HashMap::new() };
+        db.merge_text(EMBEDDED).expect("bad embedded rom database");
+        db
+    }
+
+    // the user database in the config directory, if there is one
+    pub fn user_file() -> Option<PathBuf>
+    {
+        let path = config_dir()?.join("roms.db");
+        if path.is_file()
+        {
+            Some(path)
+        }
+        else
+        {
+            None
+        }
+    }
+
+    pub fn merge_file(& mut self, path: &Path) -> Result<(), String>
+    {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.merge_text(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn lookup(&self, rom_hash: &str) -> Option<&RomInfo>
+    {
+        self.entries.get(rom_hash)
+    }
+
+    // the settings that came with the rom (from a cartridge) with its entry
+    // on top
+    pub fn settings(&self, rom: &Rom) -> RomInfo
+    {
+        let mut rom_info = rom.info.clone();
+        if let Some(entry) = self.lookup(&rom_hash(&rom.bytes))
+        {
+            rom_info.merge(entry.clone());
+        }
+        rom_info
+    }
+
+    fn merge_text(& mut self, text: &str) -> Result<(), String>
+    {
+        let mut section: Option<(String, RomInfo)> = None;
+
+        for (number, line) in text.lines().enumerate()
+        {
+            let line = line.trim();
+            let error = |message: &str| format!("line {}: {}", number + 1, message);
+
+            if line.is_empty() || line.starts_with('#')
+            {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']')
+            {
+                if let Some((hash, info)) = section.take()
+                {
+                    self.entries.entry(hash).or_default().merge(info);
+                }
+                let hash = line[1..line.len() - 1].trim().to_lowercase();
+                if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit())
+                {
+                    return Err(error("section name is not a sha-1"));
+                }
+                section = Some((hash, RomInfo::default()));
+                continue;
+            }
+
+            let info = match section
+            {
+                Some((_, ref mut info)) => info,
+                None => return Err(error("field outside of a [sha-1] section")),
+            };
+
+            let mut parts = line.splitn(2, '=');
+            let (name, value) = match (parts.next(), parts.next())
+            {
+                (Some(name), Some(value)) => (name.trim(), value.trim().to_string()),
+                _ => return Err(error("expected name = value")),
+            };
+
+            match name
+            {
+                "title" => info.title = Some(value),
+                "author" => info.author = Some(value),
+                "platform" => info.platform = Some(value),
+                "ipf" => info.cycles_per_frame = Some(value.parse().ok().filter(|&n| n > 0)
+                    .ok_or_else(|| error("ipf must be a positive number"))?),
+                "quirks" => info.quirks = Some(Quirks::preset(&value).ok_or_else(|| error("unknown quirk preset"))?),
+                "palette" => info.palette = Some(Palette::parse(&value).ok_or_else(|| error("bad palette"))?),
+                "keys" =>
+                {
+                    info.keys.clear();
+                    for hint in value.split_whitespace()
+                    {
+                        let mut parts = hint.splitn(2, '=');
+                        let action = parts.next().unwrap_or_default().replace('_', " ");
+                        let key = parts.next().and_then(|k| usize::from_str_radix(k, 16).ok()).filter(|&k| k < 16)
+                            .ok_or_else(|| error("keys must be action=key pairs with a hex key"))?;
+                        info.keys.push((action, key));
+                    }
+                },
+                _ => return Err(error(&format!("unknown field '{}'", name))),
+            }
+        }
+
+        if let Some((hash, info)) = section
+        {
+            self.entries.entry(hash).or_default().merge(info);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+// Fits the emulated framebuffer into a window of any size while keeping its
+// aspect ratio. The area outside the returned viewport is letterboxed.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScaleMode
+{
+    Integer, // only whole multiples of the native resolution, sharpest pixels
+    Stretch, // fill as much of the window as the aspect ratio allows
+}
+
+// the viewport is only needed by the window
+#[cfg(feature = "sdl")]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Viewport
+{
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Computes where a `src_width` x `src_height` image goes inside a window of
+// `dst_width` x `dst_height`. The source size is taken as a parameter (and not
+// from the SCREEN_* constants) so that larger display modes scale the same way.
+#[cfg(feature = "sdl")]
+pub fn fit(src_width: u32, src_height: u32, dst_width: u32, dst_height: u32, mode: ScaleMode) -> Viewport
+{
+    let (width, height) = match mode
+    {
+        ScaleMode::Integer if dst_width >= src_width && dst_height >= src_height =>
+        {
+            let factor = (dst_width / src_width).min(dst_height / src_height);
+            (src_width * factor, src_height * factor)
+        },
+
+        // either stretching was asked for, or the window is smaller than the
+        // native resolution and no integer factor fits
+        _ =>
+        {
+            // compare dst_width / dst_height against src_width / src_height without floats
+            if (dst_width as u64) * (src_height as u64) > (dst_height as u64) * (src_width as u64)
+            {
+                // window is wider than the image: bars on the left and right
+                ((dst_height as u64 * src_width as u64 / src_height as u64) as u32, dst_height)
+            }
+            else
+            {
+                // window is taller than the image: bars on the top and bottom
+                (dst_width, (dst_width as u64 * src_height as u64 / src_width as u64) as u32)
+            }
+        },
+    };
+
+    Viewport
+    {
+        x: ((dst_width - width) / 2) as i32,
+        y: ((dst_height - height) / 2) as i32,
+        width: width.max(1),
+        height: height.max(1),
+    }
+}
@@ -0,0 +1,95 @@
+// The window and the SDL state that goes with it. It stays open while going
+// back and forth between the launcher and the roms started from it.
+
+use sdl2::GameControllerSubsystem;
+use sdl2::Sdl;
+use sdl2::controller::GameController;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::{FullscreenType, Window};
+
+use crate::scale::{self, ScaleMode, Viewport};
+
+pub struct Screen
+{
+    pub sdl: Sdl,
+    pub canvas: Canvas<Window>,
+    game_controller: GameControllerSubsystem,
+    controllers: Vec<GameController>,
+}
+
+impl Screen
+{
+    pub fn open(width: u32, height: u32, fullscreen: bool) -> Result<Screen, String>
+    {
+        let sdl = sdl2::init()?;
+        let video_subsystem = sdl.video()?;
+        let game_controller = sdl.game_controller()?;
+
+        let mut window = video_subsystem.window("rusty-8", width, height)
+            .position_centered().resizable().build()
+            .map_err(|e| e.to_string())?;
+        if fullscreen
+        {
+            window.set_fullscreen(FullscreenType::Desktop)?;
+        }
+
+        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+
+        Ok(Screen
+        {
+            sdl,
+            canvas,
+            game_controller,
+            controllers: Vec::new(),
+        })
+    }
+
+    pub fn set_title(& mut self, title: &str)
+    {
+        // only fails on titles with a nul in them
+        let _ = self.canvas.window_mut().set_title(title);
+    }
+
+    pub fn toggle_fullscreen(& mut self) -> Result<(), String>
+    {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state()
+        {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        window.set_fullscreen(fullscreen)
+    }
+
+    // SDL reports the gamepads that are already plugged in at startup the
+    // same way as new ones, with a ControllerDeviceAdded event
+    pub fn add_controller(& mut self, joystick_index: u32)
+    {
+        if self.game_controller.is_game_controller(joystick_index)
+        {
+            match self.game_controller.open(joystick_index)
+            {
+                Ok(controller) => self.controllers.push(controller),
+                Err(e) => eprintln!("failed to open gamepad {}: {}", joystick_index, e),
+            }
+        }
+    }
+}
+
+// draws a texture of `width` x `height` pixels letterboxed into the window
+// and returns where it went. Anything drawn on top goes in before the canvas
+// is presented.
+pub fn draw_texture(canvas: &mut Canvas<Window>, texture: &Texture, width: u32, height: u32, mode: ScaleMode,
+    background: [u8; 3]) -> Result<Viewport, String>
+{
+    let (output_width, output_height) = canvas.output_size()?;
+    let viewport = scale::fit(width, height, output_width, output_height, mode);
+
+    let [r, g, b] = background;
+    canvas.set_draw_color(Color::RGB(r, g, b));
+    canvas.clear();
+    canvas.copy(texture, None, Some(Rect::new(viewport.x, viewport.y, viewport.width, viewport.height)))?;
+    Ok(viewport)
+}
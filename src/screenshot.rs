@@ -0,0 +1,60 @@
+// Writes the framebuffer to PNG files. Nothing in here depends on SDL, so the
+// same calls work from the F12 hotkey and from headless code.
+
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::palette::Palette;
+
+pub fn save_png(path: &Path, gfx: &[u8], width: usize, height: usize, palette: &Palette, scale: usize) -> io::Result<()>
+{
+    let file = BufWriter::new(File::create(path)?);
+
+    let mut encoder = png::Encoder::new(file, (width * scale) as u32, (height * scale) as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&palette.render(gfx, width, height, scale)).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+// builds a name like "tetris-20190312-221503.png" from the rom path, so that
+// captures of different games (and of the same game) never overwrite each
+// other. A second capture within the same second gets "-2" and so on.
+pub fn file_name(rom_file: &str, extension: &str) -> String
+{
+    let rom_name = Path::new(rom_file).file_stem().and_then(|s| s.to_str()).unwrap_or("rusty-8");
+    let stem = format!("{}-{}", rom_name, timestamp());
+    let mut name = format!("{}.{}", stem, extension);
+    let mut count = 1;
+    while Path::new(&name).exists()
+    {
+        count += 1;
+        name = format!("{}-{}.{}", stem, count, extension);
+    }
+    name
+}
+
+// current UTC time as YYYYMMDD-HHMMSS
+fn timestamp() -> String
+{
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+
+    // civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, secs / 3600, (secs / 60) % 60, secs % 60)
+}
@@ -0,0 +1,208 @@
+// Terminal output and input for the frontends that don't need SDL: the 64x32
+// screen drawn with unicode half blocks (64x16 characters) or braille
+// (32x8), a guard that puts the terminal into raw mode on the alternate
+// screen, redraws that only send the lines that changed, and a keypad for
+// terminals that don't report key releases.
+
+use std::io::{self, Write};
+
+use crossterm::event::{DisableBracketedPaste, EnableBracketedPaste};
+use crossterm::event::{KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use crossterm::{cursor, execute, queue, terminal};
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+// frames a key stays down after a press when releases aren't reported. Key
+// repeat kicks in well within this, so a held key stays down.
+const KEY_HOLD_FRAMES: u32 = 30;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Glyphs
+{
+    HalfBlocks,
+    Braille,
+}
+
+impl Glyphs
+{
+    pub fn other(self) -> Glyphs
+    {
+        match self
+        {
+            Glyphs::HalfBlocks => Glyphs::Braille,
+            Glyphs::Braille => Glyphs::HalfBlocks,
+        }
+    }
+}
+
+fn lit(gfx: &[u8], x: usize, y: usize) -> bool
+{
+    gfx[y * WIDTH + x] != 0
+}
+
+// the screen as lines of text, top to bottom
+pub fn render(gfx: &[u8], glyphs: Glyphs) -> Vec<String>
+{
+    match glyphs
+    {
+        // a character is two pixels high, the upper one in the top half
+        Glyphs::HalfBlocks => (0..HEIGHT / 2).map(|row|
+        {
+            (0..WIDTH).map(|x| match (lit(gfx, x, row * 2), lit(gfx, x, row * 2 + 1))
+            {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            }).collect()
+        }).collect(),
+
+        // a character is two pixels wide and four high, one braille dot each
+        Glyphs::Braille => (0..HEIGHT / 4).map(|row|
+        {
+            (0..WIDTH / 2).map(|column|
+            {
+                const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+                let mut bits = 0;
+                for (dx, dots) in DOTS.iter().enumerate()
+                {
+                    for (dy, &dot) in dots.iter().enumerate()
+                    {
+                        if lit(gfx, column * 2 + dx, row * 4 + dy)
+                        {
+                            bits |= dot;
+                        }
+                    }
+                }
+                char::from_u32(0x2800 + bits).unwrap()
+            }).collect()
+        }).collect(),
+    }
+}
+
+// raw mode on the alternate screen with the cursor hidden, for as long as
+// it's alive. Pastes come in whole, and terminals that can report key
+// releases are asked to.
+pub struct RawTerminal
+{
+    enhanced: bool,
+}
+
+impl RawTerminal
+{
+    pub fn enter() -> io::Result<RawTerminal>
+    {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        execute!(out, terminal::EnterAlternateScreen, terminal::DisableLineWrap, cursor::Hide, EnableBracketedPaste)?;
+
+        let enhanced = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if enhanced
+        {
+            execute!(out, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        Ok(RawTerminal { enhanced })
+    }
+}
+
+impl Drop for RawTerminal
+{
+    fn drop(& mut self)
+    {
+        // nothing to be done if the terminal can't be put back
+        let mut out = io::stdout();
+        if self.enhanced
+        {
+            let _ = execute!(out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(out, DisableBracketedPaste, cursor::Show, terminal::EnableLineWrap, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+        let _ = out.flush();
+    }
+}
+
+// what's on the terminal, so a redraw only has to send the lines that
+// changed. That keeps 60 frames a second within reach of a slow connection.
+pub struct Lines
+{
+    shown: Vec<String>,
+}
+
+impl Lines
+{
+    pub fn new() -> Lines
+    {
+        Lines { shown: Vec::new() }
+    }
+
+    pub fn draw(& mut self, out: &mut impl Write, lines: Vec<String>) -> io::Result<()>
+    {
+        for (row, line) in lines.iter().enumerate()
+        {
+            if self.shown.get(row) != Some(line)
+            {
+                queue!(out, cursor::MoveTo(0, row as u16), terminal::Clear(terminal::ClearType::UntilNewLine))?;
+                write!(out, "{}", line)?;
+            }
+        }
+        if lines.len() < self.shown.len()
+        {
+            queue!(out, cursor::MoveTo(0, lines.len() as u16), terminal::Clear(terminal::ClearType::FromCursorDown))?;
+        }
+        self.shown = lines;
+        out.flush()
+    }
+
+    // clears the terminal, after a resize, so the next draw sends everything
+    pub fn clear(& mut self, out: &mut impl Write) -> io::Result<()>
+    {
+        self.shown.clear();
+        queue!(out, terminal::Clear(terminal::ClearType::All))
+    }
+}
+
+// the hex keypad fed from terminal key events. Most terminals only send
+// presses (and repeats while a key is held), so a key is let go a while
+// after its last press, unless a release comes first.
+pub struct Keypad
+{
+    held: [u32; 16], // frames left until each key is let go
+    releases: bool,  // the terminal reports releases, so keys stay down until then
+}
+
+impl Keypad
+{
+    pub fn new() -> Keypad
+    {
+        Keypad { held: [0; 16], releases: false }
+    }
+
+    pub fn press(& mut self, key: usize)
+    {
+        self.held[key] = if self.releases { u32::MAX } else { KEY_HOLD_FRAMES };
+    }
+
+    pub fn release(& mut self, key: usize)
+    {
+        self.releases = true;
+        self.held[key] = 0;
+    }
+
+    // counts down the keys held by a press, once a frame
+    pub fn frame(& mut self)
+    {
+        for held in self.held.iter_mut().filter(|held| **held != u32::MAX)
+        {
+            *held = held.saturating_sub(1);
+        }
+    }
+
+    pub fn apply(&self, key: &mut [u8; 16])
+    {
+        for (key, &held) in key.iter_mut().zip(self.held.iter())
+        {
+            *key = (held > 0) as u8;
+        }
+    }
+}
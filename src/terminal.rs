@@ -0,0 +1,231 @@
+// The terminal frontend, `rusty-8 run --terminal`, and the only one in a
+// build without SDL. The screen is drawn in the palette colors with half
+// blocks or braille (--braille, F2 switches) at 60 Hz, sending only the lines
+// that changed. Keypad keys are let go shortly after their last key press
+// unless the terminal reports releases, and the terminal bell rings when the
+// sound timer starts.
+//
+// The controls are the window's, except that tab switches fast forward on
+// and off, as not every terminal says when a key is let go. Dropping a file
+// onto a terminal pastes its path, which runs it like a drop onto the window.
+
+use std::io::{self, IsTerminal, Stdout, Write};
+use std::time::Instant;
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Stylize};
+use crossterm::terminal;
+
+use crate::cli::RunOptions;
+use crate::cpu::Fault;
+use crate::frontend::{self, Action, Audio, Display, Exit, Frame, Input};
+use crate::term::{self, Glyphs, Keypad, Lines, RawTerminal};
+use crate::Game;
+
+struct Terminal
+{
+    out: Stdout,
+    lines: Lines,
+    keypad: Keypad,
+    glyphs: Glyphs,
+    message: Option<String>, // the last notice, until the next key press
+    sound_on: bool,
+    fast_forward: bool,
+}
+
+fn color([r, g, b]: [u8; 3]) -> Color
+{
+    Color::Rgb { r, g, b }
+}
+
+fn terminal_error(e: io::Error) -> String
+{
+    format!("terminal error: {}", e)
+}
+
+impl Terminal
+{
+    fn status_line(&self, game: &Game, frame: &Frame) -> String
+    {
+        let title = game.rom_info.name().unwrap_or_else(|| game.rom.name.clone());
+        let status = frame.status(game);
+        let state = match self.message
+        {
+            Some(ref message) if frame.fault.is_none() => message.clone(),
+            _ if !status.is_empty() => status.join(", "),
+            _ => "esc quit  F5 reset  F6 pause  F2 braille".to_string(),
+        };
+        format!(" {}  {}", title, state)
+    }
+
+    fn read_input(& mut self, game: &Game, deadline: Instant) -> io::Result<Vec<Action>>
+    {
+        let mut actions = Vec::new();
+        while event::poll(deadline.saturating_duration_since(Instant::now()))?
+        {
+            let key = match event::read()?
+            {
+                Event::Key(key) => key,
+                Event::Resize(..) =>
+                {
+                    self.lines.clear(&mut self.out)?;
+                    continue;
+                },
+                // terminals quote paths with spaces in them when they're dropped
+                Event::Paste(text) =>
+                {
+                    let path = text.trim().trim_matches(|c| c == '\'' || c == '"');
+                    if !path.is_empty()
+                    {
+                        actions.push(Action::Load(path.to_string()));
+                    }
+                    continue;
+                },
+                _ => continue,
+            };
+
+            if key.kind == KeyEventKind::Release
+            {
+                if let KeyCode::Char(c) = key.code
+                {
+                    if let Some(k) = game.keymap.key(c)
+                    {
+                        self.keypad.release(k);
+                    }
+                }
+                continue;
+            }
+
+            // held keys repeat, which would switch pause and the like back and
+            // forth; F7 keeps stepping, as it does in the window
+            if key.kind == KeyEventKind::Repeat && !matches!(key.code, KeyCode::Char(_) | KeyCode::F(7))
+            {
+                continue;
+            }
+
+            self.message = None;
+            let shift = key.modifiers.contains(KeyModifiers::SHIFT);
+            match key.code
+            {
+                KeyCode::Esc => return Ok(vec![Action::Menu]),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(vec![Action::Quit]),
+
+                KeyCode::F(5) => actions.push(Action::Reset { hard: shift }),
+                KeyCode::F(6) => actions.push(Action::Pause),
+                KeyCode::F(7) => actions.push(if shift { Action::Step } else { Action::Advance }),
+                KeyCode::Tab if key.kind == KeyEventKind::Press =>
+                {
+                    self.fast_forward = !self.fast_forward;
+                    actions.push(Action::FastForward(self.fast_forward));
+                },
+                KeyCode::F(8) => actions.push(Action::SlowMotion),
+                KeyCode::F(2) => self.glyphs = self.glyphs.other(),
+                KeyCode::F(10) => actions.push(Action::Record),
+                KeyCode::F(11) => actions.push(Action::Trace),
+                KeyCode::F(12) => actions.push(Action::Screenshot { native: shift }),
+
+                KeyCode::Char(c) =>
+                {
+                    if let Some(k) = game.keymap.key(c)
+                    {
+                        self.keypad.press(k);
+                    }
+                },
+                _ => {},
+            }
+        }
+        Ok(actions)
+    }
+
+    fn draw_screen(& mut self, game: &Game, frame: &Frame) -> io::Result<()>
+    {
+        let (foreground, background) = (color(game.palette.foreground), color(game.palette.background));
+        let (_, rows) = terminal::size()?;
+        let mut screen: Vec<String> = term::render(&game.vm.gfx, self.glyphs).into_iter()
+            .map(|line| line.with(foreground).on(background).to_string())
+            .collect();
+        screen.truncate(rows.saturating_sub(1) as usize);
+        screen.push(self.status_line(game, frame));
+        self.lines.draw(&mut self.out, screen)
+    }
+}
+
+impl Display for Terminal
+{
+    fn notify(& mut self, text: &str)
+    {
+        self.message = Some(text.to_string());
+    }
+
+    fn error(& mut self, text: &str)
+    {
+        self.message = Some(text.to_string());
+    }
+
+    // redrawn every frame, the status line changes too and Lines only sends
+    // what's different
+    fn draw(& mut self, game: &Game, frame: &Frame) -> Result<(), String>
+    {
+        self.draw_screen(game, frame).map_err(terminal_error)
+    }
+}
+
+impl Audio for Terminal
+{
+    fn sound(& mut self, _started: bool, playing: bool)
+    {
+        if playing && !self.sound_on
+        {
+            // a bell that doesn't make it out is no reason to stop
+            let _ = write!(self.out, "\x07");
+        }
+        self.sound_on = playing;
+    }
+}
+
+impl Input for Terminal
+{
+    fn poll(& mut self, game: &Game, _frame: &Frame, deadline: Instant) -> Result<Vec<Action>, String>
+    {
+        let actions = self.read_input(game, deadline).map_err(terminal_error)?;
+        self.keypad.frame();
+        Ok(actions)
+    }
+
+    fn keypad(& mut self, keys: &mut [u8; 16])
+    {
+        self.keypad.apply(keys);
+    }
+}
+
+// the screen is drawn to stdout, which can't also be a pipe or a file
+pub fn check() -> Result<(), String>
+{
+    if io::stdout().is_terminal()
+    {
+        Ok(())
+    }
+    else
+    {
+        Err("playing in the terminal needs stdout to be a terminal, use --headless to run without one".to_string())
+    }
+}
+
+// plays until escape and returns how the VM fared; only terminal errors end
+// it early
+pub fn run(game: &mut Game, options: &RunOptions) -> Result<(Exit, Result<(), Fault>), String>
+{
+    check()?;
+    let _terminal = RawTerminal::enter().map_err(terminal_error)?;
+    let mut terminal = Terminal
+    {
+        out: io::stdout(),
+        lines: Lines::new(),
+        keypad: Keypad::new(),
+        glyphs: if options.braille { Glyphs::Braille } else { Glyphs::HalfBlocks },
+        message: None,
+        sound_on: false,
+        fast_forward: false,
+    };
+    frontend::run(game, options, &mut terminal)
+}
@@ -0,0 +1,349 @@
+// Instruction traces for --trace: the state of the VM before every
+// instruction it runs, for comparing a run with another emulator's, or with
+// an earlier one of ours. A trace can be limited to a range of pc and turned
+// on and off while the game runs (F11), to keep it to the interesting part.
+//
+// The text format has a line per instruction, every field but the mnemonic
+// fixed width, and # comments:
+//
+//   0000000042 PC=20A OP=6A02 V=00000000000000000000000000000000 I=2EA SP=01 DT=3C ST=00 LD VA, 0x02
+//
+// that is the cycle (instructions run before this one, traced or not, in
+// decimal), pc, the opcode, V0 to VF, I, the stack pointer and the delay and
+// sound timers. Files ending in .bin get the binary format instead: "R8TRACE"
+// and a version byte, then a 34 byte record per instruction, little endian:
+// cycle u64, pc u16, opcode u16, V0 to VF, I u16, sp, dt, st and a zero.
+//
+// Reader reads either format back, for tracediff. Traces made by another
+// emulator only need the fields up to ST in the text format; the cycle is
+// just shown, as not every emulator counts from 0.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::ops::RangeInclusive;
+
+use crate::cpu::VM;
+use crate::disasm;
+
+const MAGIC: &[u8; 7] = b"R8TRACE";
+const VERSION: u8 = 1;
+pub const RECORD_SIZE: usize = 34;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TraceFormat
+{
+    Text,
+    Binary,
+}
+
+impl TraceFormat
+{
+    // *.bin is binary, everything else, stdout included, is text
+    pub fn from_path(path: &str) -> TraceFormat
+    {
+        if path.to_lowercase().ends_with(".bin")
+        {
+            TraceFormat::Binary
+        }
+        else
+        {
+            TraceFormat::Text
+        }
+    }
+}
+
+// the VM just before it runs an instruction
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Entry
+{
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub sp: u8,
+    pub dt: u8,
+    pub st: u8,
+}
+
+impl Entry
+{
+    pub fn capture(vm: &VM, cycle: u64) -> Entry
+    {
+        let registers = vm.registers();
+        let memory = vm.memory();
+
+        // pc can run off the end of memory, which faults rather than runs
+        let byte = |address: usize| *memory.get(address).unwrap_or(&0) as u16;
+        let pc = registers.pc as usize;
+        Entry
+        {
+            cycle,
+            pc: registers.pc,
+            opcode: byte(pc) << 8 | byte(pc + 1),
+            v: registers.v,
+            i: registers.i,
+            sp: registers.sp as u8,
+            dt: registers.delay_timer,
+            st: registers.sound_timer,
+        }
+    }
+
+    pub fn text(&self) -> String
+    {
+        let v: String = self.v.iter().map(|v| format!("{:02X}", v)).collect();
+        format!("{:010} PC={:03X} OP={:04X} V={} I={:03X} SP={:02X} DT={:02X} ST={:02X} {}", self.cycle, self.pc,
+            self.opcode, v, self.i, self.sp, self.dt, self.st, disasm::disassemble(self.opcode))
+    }
+
+    // a line of the text format, None if it isn't one
+    pub fn parse(line: &str) -> Option<Entry>
+    {
+        let mut fields = line.split_whitespace();
+        let mut entry = Entry
+        {
+            cycle: fields.next()?.parse().ok()?,
+            pc: 0, opcode: 0, v: [0; 16], i: 0, sp: 0, dt: 0, st: 0,
+        };
+
+        // the mnemonic after them is free text
+        let mut count = 0;
+        for field in fields.take(7)
+        {
+            count += 1;
+            let (key, value) = field.split_once('=')?;
+            match key
+            {
+                "PC" => entry.pc = u16::from_str_radix(value, 16).ok()?,
+                "OP" => entry.opcode = u16::from_str_radix(value, 16).ok()?,
+                "I" => entry.i = u16::from_str_radix(value, 16).ok()?,
+                "SP" => entry.sp = u8::from_str_radix(value, 16).ok()?,
+                "DT" => entry.dt = u8::from_str_radix(value, 16).ok()?,
+                "ST" => entry.st = u8::from_str_radix(value, 16).ok()?,
+                "V" if value.len() == 32 => for (n, v) in entry.v.iter_mut().enumerate()
+                {
+                    *v = u8::from_str_radix(value.get(n * 2..n * 2 + 2)?, 16).ok()?;
+                },
+                _ => return None,
+            }
+        }
+        Some(entry).filter(|_| count == 7)
+    }
+
+    fn from_binary(record: &[u8; RECORD_SIZE]) -> Entry
+    {
+        let word = |at: usize| u16::from_le_bytes([record[at], record[at + 1]]);
+        let mut cycle = [0; 8];
+        cycle.copy_from_slice(&record[0..8]);
+        let mut v = [0; 16];
+        v.copy_from_slice(&record[12..28]);
+        Entry
+        {
+            cycle: u64::from_le_bytes(cycle),
+            pc: word(8),
+            opcode: word(10),
+            v,
+            i: word(28),
+            sp: record[30],
+            dt: record[31],
+            st: record[32],
+        }
+    }
+
+    // the names of the fields that differ, the timers only when asked to as
+    // they depend on how an emulator lines instructions up with frames
+    pub fn differences(&self, other: &Entry, timers: bool) -> Vec<String>
+    {
+        let mut fields = Vec::new();
+        if self.pc != other.pc
+        {
+            fields.push("PC".to_string());
+        }
+        if self.opcode != other.opcode
+        {
+            fields.push("opcode".to_string());
+        }
+        fields.extend((0..16).filter(|&n| self.v[n] != other.v[n]).map(|n| format!("V{:X}", n)));
+        if self.i != other.i
+        {
+            fields.push("I".to_string());
+        }
+        if self.sp != other.sp
+        {
+            fields.push("SP".to_string());
+        }
+        if timers && self.dt != other.dt
+        {
+            fields.push("DT".to_string());
+        }
+        if timers && self.st != other.st
+        {
+            fields.push("ST".to_string());
+        }
+        fields
+    }
+
+    pub fn binary(&self) -> [u8; RECORD_SIZE]
+    {
+        let mut record = [0; RECORD_SIZE];
+        record[0..8].copy_from_slice(&self.cycle.to_le_bytes());
+        record[8..10].copy_from_slice(&self.pc.to_le_bytes());
+        record[10..12].copy_from_slice(&self.opcode.to_le_bytes());
+        record[12..28].copy_from_slice(&self.v);
+        record[28..30].copy_from_slice(&self.i.to_le_bytes());
+        record[30] = self.sp;
+        record[31] = self.dt;
+        record[32] = self.st;
+        record
+    }
+}
+
+pub struct Trace
+{
+    out: Box<dyn Write>,
+    format: TraceFormat,
+    range: RangeInclusive<u16>, // pc of the instructions traced
+    enabled: bool,
+    cycle: u64,
+}
+
+impl Trace
+{
+    pub fn create(path: &str, range: RangeInclusive<u16>, enabled: bool) -> io::Result<Trace>
+    {
+        let format = TraceFormat::from_path(path);
+        let mut out: Box<dyn Write> = if path == "-"
+        {
+            Box::new(BufWriter::new(io::stdout()))
+        }
+        else
+        {
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+
+        match format
+        {
+            TraceFormat::Text => writeln!(out, "# rusty-8 trace: cycle pc opcode V0-VF I SP DT ST mnemonic")?,
+            TraceFormat::Binary =>
+            {
+                out.write_all(MAGIC)?;
+                out.write_all(&[VERSION])?;
+            },
+        }
+        Ok(Trace { out, format, range, enabled, cycle: 0 })
+    }
+
+    // called before every instruction, whether it's traced or not, to keep
+    // count of the cycles
+    pub fn record(& mut self, vm: &VM) -> io::Result<()>
+    {
+        let cycle = self.cycle;
+        self.cycle += 1;
+        if !self.enabled || !self.range.contains(&vm.registers().pc)
+        {
+            return Ok(());
+        }
+
+        let entry = Entry::capture(vm, cycle);
+        match self.format
+        {
+            TraceFormat::Text => writeln!(self.out, "{}", entry.text()),
+            TraceFormat::Binary => self.out.write_all(&entry.binary()),
+        }
+    }
+
+    // turns tracing on or off and says which it is now
+    pub fn toggle(& mut self) -> bool
+    {
+        self.enabled = !self.enabled;
+        self.enabled
+    }
+
+    pub fn finish(mut self) -> io::Result<()>
+    {
+        self.out.flush()
+    }
+}
+
+// the entries of a trace file in either format, one at a time, as traces of
+// long runs get big
+pub struct Reader
+{
+    input: BufReader<File>,
+    format: TraceFormat,
+    line: usize,
+}
+
+fn invalid(message: String) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Reader
+{
+    // binary traces are told apart by their header, not their name
+    pub fn open(path: &str) -> io::Result<Reader>
+    {
+        let mut input = BufReader::new(File::open(path)?);
+        let format = if input.fill_buf()?.starts_with(MAGIC)
+        {
+            let mut header = [0; 8];
+            input.read_exact(&mut header)?;
+            if header[7] != VERSION
+            {
+                return Err(invalid(format!("binary trace version {} isn't supported", header[7])));
+            }
+            TraceFormat::Binary
+        }
+        else
+        {
+            TraceFormat::Text
+        };
+        Ok(Reader { input, format, line: 0 })
+    }
+
+    fn next_text(& mut self) -> io::Result<Option<Entry>>
+    {
+        let mut line = String::new();
+        loop
+        {
+            line.clear();
+            if self.input.read_line(&mut line)? == 0
+            {
+                return Ok(None);
+            }
+            self.line += 1;
+            if line.trim().is_empty() || line.starts_with('#')
+            {
+                continue;
+            }
+            return Entry::parse(&line).map(Some)
+                .ok_or_else(|| invalid(format!("line {} isn't a trace entry", self.line)));
+        }
+    }
+
+    fn next_binary(& mut self) -> io::Result<Option<Entry>>
+    {
+        if self.input.fill_buf()?.is_empty()
+        {
+            return Ok(None);
+        }
+        let mut record = [0; RECORD_SIZE];
+        self.input.read_exact(&mut record).map_err(|_| invalid("the last record is cut short".to_string()))?;
+        Ok(Some(Entry::from_binary(&record)))
+    }
+}
+
+impl Iterator for Reader
+{
+    type Item = io::Result<Entry>;
+
+    fn next(& mut self) -> Option<io::Result<Entry>>
+    {
+        match self.format
+        {
+            TraceFormat::Text => self.next_text(),
+            TraceFormat::Binary => self.next_binary(),
+        }.transpose()
+    }
+}
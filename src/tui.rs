@@ -0,0 +1,425 @@
+// The terminal debugger, `rusty-8 tui`, for machines without SDL or a
+// display, such as over ssh. The screen is drawn with half blocks or braille
+// (F2 switches) next to panes with the registers and keypad, the
+// disassembly around pc, the stack and memory. It is a frontend like the
+// others, so frontend::run does the timing, the speed controls and the
+// captures.
+//
+// It starts paused. Space runs and pauses, enter steps one instruction and
+// F7 one frame. Up and down move the disassembly cursor, F9 sets or clears a
+// breakpoint there, and execution stops before running an instruction with a
+// breakpoint. Page up and down scroll memory, escape or ctrl+c quits. The
+// keypad keys go to the VM as in the window, and F5, tab, F8, F10, F11 and
+// F12 are the terminal frontend's.
+//
+// Lines are only rewritten when they change, which keeps it usable over a
+// slow connection.
+
+use std::io::{self, Stdout};
+use std::time::Instant;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::Stylize;
+use crossterm::terminal;
+
+use crate::cli::RunOptions;
+use crate::cpu::Fault;
+use crate::disasm;
+use crate::frontend::{self, Action, Audio, Display, Exit, Frame, Input};
+use crate::term::{self, Glyphs, Keypad, Lines, RawTerminal};
+use crate::Game;
+
+const REGISTERS_WIDTH: usize = 24;
+const DISASM_WIDTH: usize = 36;
+const STACK_WIDTH: usize = 17;
+const BYTES_PER_ROW: usize = 8;
+const MEMORY_WIDTH: usize = 5 + BYTES_PER_ROW * 3 + 3;
+const MEMORY_SIZE: usize = 4096;
+
+struct Debugger
+{
+    out: Stdout,
+    lines: Lines,
+    keypad: Keypad,
+    glyphs: Glyphs,
+    fast_forward: bool,
+
+    cursor: u16,       // disassembly cursor, back on pc whenever execution stops
+    follow: bool,      // execution went on since the cursor was last put on pc
+    memory_top: usize, // first row of the memory pane
+    memory_rows: usize,
+    message: Option<String>, // the last notice, until the next key press
+}
+
+fn terminal_error(e: io::Error) -> String
+{
+    format!("terminal error: {}", e)
+}
+
+// the width of text as shown, leaving out the escape sequences for styles
+fn visible_width(text: &str) -> usize
+{
+    let mut width = 0;
+    let mut escape = false;
+    for c in text.chars()
+    {
+        match c
+        {
+            '\x1b' => escape = true,
+            'm' if escape => escape = false,
+            _ if escape => {},
+            _ => width += 1,
+        }
+    }
+    width
+}
+
+// `lines` in a box `width` x `height` characters, border included
+fn pane(title: &str, lines: &[String], width: usize, height: usize) -> Vec<String>
+{
+    let inner = width - 2;
+    let mut out = vec![format!("┌{:─<inner$}┐", format!("─{}", title), inner = inner)];
+    for row in 0..height - 2
+    {
+        let line = lines.get(row).map(String::as_str).unwrap_or("");
+        let padding = inner.saturating_sub(visible_width(line));
+        out.push(format!("│{}{}│", line, " ".repeat(padding)));
+    }
+    out.push(format!("└{}┘", "─".repeat(inner)));
+    out
+}
+
+// panes of the same height next to each other
+fn beside(panes: Vec<Vec<String>>) -> Vec<String>
+{
+    (0..panes[0].len()).map(|row| panes.iter().map(|pane| pane[row].as_str()).collect()).collect()
+}
+
+impl Debugger
+{
+    fn scroll_memory(& mut self, rows: isize)
+    {
+        let last = (MEMORY_SIZE / BYTES_PER_ROW).saturating_sub(self.memory_rows) as isize;
+        self.memory_top = (self.memory_top as isize + rows).clamp(0, last) as usize;
+    }
+
+    // the controls for the runner, if the key is one
+    fn key(& mut self, game: &Game, event: KeyEvent) -> Option<Action>
+    {
+        if event.kind == KeyEventKind::Release
+        {
+            if let KeyCode::Char(c) = event.code
+            {
+                if let Some(key) = game.keymap.key(c)
+                {
+                    self.keypad.release(key);
+                }
+            }
+            return None;
+        }
+
+        // held keys repeat, which is only wanted for the keypad, stepping and
+        // moving around
+        let repeats = match event.code
+        {
+            KeyCode::Char(c) => c != ' ',
+            KeyCode::Enter | KeyCode::F(7) | KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown => true,
+            _ => false,
+        };
+        if event.kind == KeyEventKind::Repeat && !repeats
+        {
+            return None;
+        }
+
+        self.message = None;
+        let shift = event.modifiers.contains(KeyModifiers::SHIFT);
+        match event.code
+        {
+            KeyCode::Esc => return Some(Action::Quit),
+            KeyCode::Char('c') if event.modifiers.contains(KeyModifiers::CONTROL) => return Some(Action::Quit),
+
+            KeyCode::Char(' ') => return Some(Action::Pause),
+            KeyCode::Enter =>
+            {
+                self.follow = true;
+                return Some(Action::Step);
+            },
+            KeyCode::F(7) =>
+            {
+                self.follow = true;
+                return Some(Action::Advance);
+            },
+            KeyCode::F(9) => return Some(Action::Breakpoint(self.cursor)),
+
+            KeyCode::F(5) => return Some(Action::Reset { hard: shift }),
+            KeyCode::Tab if event.kind == KeyEventKind::Press =>
+            {
+                self.fast_forward = !self.fast_forward;
+                return Some(Action::FastForward(self.fast_forward));
+            },
+            KeyCode::F(8) => return Some(Action::SlowMotion),
+            KeyCode::F(10) => return Some(Action::Record),
+            KeyCode::F(11) => return Some(Action::Trace),
+            KeyCode::F(12) => return Some(Action::Screenshot { native: shift }),
+
+            KeyCode::Up => self.cursor = self.cursor.saturating_sub(2),
+            KeyCode::Down => self.cursor = (self.cursor + 2).min(MEMORY_SIZE as u16 - 2),
+            KeyCode::Home => self.cursor = game.vm.registers().pc,
+            KeyCode::PageUp => self.scroll_memory(-(self.memory_rows as isize)),
+            KeyCode::PageDown => self.scroll_memory(self.memory_rows as isize),
+            KeyCode::F(2) => self.glyphs = self.glyphs.other(),
+
+            KeyCode::Char(c) =>
+            {
+                if let Some(key) = game.keymap.key(c)
+                {
+                    self.keypad.press(key);
+                }
+            },
+            _ => {},
+        }
+        None
+    }
+
+    fn read_input(& mut self, game: &Game, deadline: Instant) -> io::Result<Vec<Action>>
+    {
+        let mut actions = Vec::new();
+        while event::poll(deadline.saturating_duration_since(Instant::now()))?
+        {
+            match event::read()?
+            {
+                Event::Key(key) => actions.extend(self.key(game, key)),
+                Event::Resize(..) => self.lines.clear(&mut self.out)?,
+                _ => {},
+            }
+            if matches!(actions.last(), Some(Action::Quit))
+            {
+                break;
+            }
+        }
+        Ok(actions)
+    }
+
+    fn registers_lines(&self, game: &Game) -> Vec<String>
+    {
+        let registers = game.vm.registers();
+        let mut lines: Vec<String> = (0..8).map(|row|
+        {
+            format!(" V{:X} {:02X}   V{:X} {:02X}", row, registers.v[row], row + 8, registers.v[row + 8])
+        }).collect();
+        lines.push(format!(" PC {:03X}   I {:03X}", registers.pc, registers.i));
+        lines.push(format!(" DT {:02X}    ST {:02X}", registers.delay_timer, registers.sound_timer));
+        lines.push(format!(" frame {} +{}", game.session.frame, game.session.cycle));
+        lines.push(String::new());
+
+        // the keypad as laid out on the COSMAC VIP, pressed keys in reverse
+        for keys in [[0x1, 0x2, 0x3, 0xC], [0x4, 0x5, 0x6, 0xD], [0x7, 0x8, 0x9, 0xE], [0xA, 0x0, 0xB, 0xF]]
+        {
+            let mut line = String::from(" ");
+            for key in keys
+            {
+                let text = format!("{:X}", key);
+                if game.vm.key[key] != 0
+                {
+                    line += &format!("{} ", text.reverse());
+                }
+                else
+                {
+                    line += &format!("{} ", text);
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    // pc's line in reverse, the cursor with a > and breakpoints with a *
+    fn disassembly_lines(&self, game: &Game, rows: usize) -> Vec<String>
+    {
+        let memory = game.vm.memory();
+        let pc = game.vm.registers().pc as usize;
+        let first = (self.cursor as usize).saturating_sub(rows / 3 * 2);
+
+        (0..rows).map(|row| first + row * 2).take_while(|&address| address + 1 < memory.len()).map(|address|
+        {
+            let opcode = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+            let marks = format!("{}{}",
+                if address == self.cursor as usize { '>' } else { ' ' },
+                if game.session.breakpoints.contains(&(address as u16)) { '*' } else { ' ' });
+            let text = format!("{:03X}: {:04X}  {}", address, opcode, disasm::disassemble(opcode));
+            if address == pc
+            {
+                format!("{}{}", marks, text.reverse())
+            }
+            else
+            {
+                format!("{}{}", marks, text)
+            }
+        }).collect()
+    }
+
+    // the innermost call first
+    fn stack_lines(&self, game: &Game) -> Vec<String>
+    {
+        let registers = game.vm.registers();
+        (0..registers.sp as usize).rev()
+            .map(|level| format!(" {:2} {:03X} > {:03X}", level, registers.stack[level], registers.stack[level] + 2))
+            .collect()
+    }
+
+    // pc's bytes in reverse and the byte at I underlined
+    fn memory_lines(&self, game: &Game, rows: usize) -> Vec<String>
+    {
+        let memory = game.vm.memory();
+        let registers = game.vm.registers();
+        let (pc, i) = (registers.pc as usize, registers.i as usize);
+
+        (self.memory_top..self.memory_top + rows).take_while(|&row| row * BYTES_PER_ROW < memory.len()).map(|row|
+        {
+            let start = row * BYTES_PER_ROW;
+            let mut line = format!(" {:03X}:", start);
+            for (address, byte) in memory.iter().enumerate().skip(start).take(BYTES_PER_ROW)
+            {
+                let text = format!("{:02X}", byte);
+                line += " ";
+                if address == pc || address == pc + 1
+                {
+                    line += &text.reverse().to_string();
+                }
+                else if address == i
+                {
+                    line += &text.underlined().to_string();
+                }
+                else
+                {
+                    line += &text;
+                }
+            }
+            line
+        }).collect()
+    }
+
+    fn status_line(&self, frame: &Frame, running: bool) -> String
+    {
+        let state = match (frame.fault.is_some(), running)
+        {
+            (true, _) => "faulted",
+            (false, true) => "running",
+            (false, false) => "paused",
+        };
+        let mut line = match (frame.fault, &self.message)
+        {
+            (Some(fault), _) => format!(" {}: vm fault: {}, F5 resets", state, fault),
+            (None, Some(message)) => format!(" {}: {}", state, message),
+            (None, None) => format!(" {}: space run  enter step  F7 frame  F9 breakpoint  F2 braille  esc quit", state),
+        };
+        if frame.speed != 1.0
+        {
+            line += &format!("  speed {}x", frame.speed);
+        }
+        line
+    }
+
+    // every line of the terminal, `rows` of them
+    fn screen_lines(& mut self, game: &Game, frame: &Frame, rows: usize) -> Vec<String>
+    {
+        let screen = term::render(&game.vm.gfx, self.glyphs);
+        let screen_width = screen[0].chars().count() + 2;
+        let top = beside(vec![pane("screen", &screen, screen_width, 18),
+            pane("registers", &self.registers_lines(game), REGISTERS_WIDTH, 18)]);
+
+        let height = rows.saturating_sub(top.len() + 1).max(4);
+        self.memory_rows = height - 2;
+        self.scroll_memory(0);
+        let bottom = beside(vec![pane("disassembly", &self.disassembly_lines(game, height - 2), DISASM_WIDTH, height),
+            pane("stack", &self.stack_lines(game), STACK_WIDTH, height),
+            pane("memory", &self.memory_lines(game, height - 2), MEMORY_WIDTH, height)]);
+
+        let running = !game.session.paused && frame.fault.is_none();
+        let mut lines = top;
+        lines.extend(bottom);
+        lines.truncate(rows.saturating_sub(1));
+        lines.push(self.status_line(frame, running));
+        lines
+    }
+
+    fn draw_screen(& mut self, game: &Game, frame: &Frame) -> io::Result<()>
+    {
+        // once execution stops, by a pause, a step, a breakpoint or a fault,
+        // the cursor goes back to pc
+        if !game.session.paused && frame.fault.is_none()
+        {
+            self.follow = true;
+        }
+        else if self.follow
+        {
+            self.cursor = game.vm.registers().pc;
+            self.follow = false;
+        }
+
+        let (_, rows) = terminal::size()?;
+        let lines = self.screen_lines(game, frame, rows as usize);
+        self.lines.draw(&mut self.out, lines)
+    }
+}
+
+impl Display for Debugger
+{
+    fn notify(& mut self, text: &str)
+    {
+        self.message = Some(text.to_string());
+    }
+
+    fn error(& mut self, text: &str)
+    {
+        self.message = Some(text.to_string());
+    }
+
+    fn draw(& mut self, game: &Game, frame: &Frame) -> Result<(), String>
+    {
+        self.draw_screen(game, frame).map_err(terminal_error)
+    }
+}
+
+// a bell at every beep would only get in the way of stepping through
+impl Audio for Debugger
+{
+    fn sound(& mut self, _started: bool, _playing: bool) {}
+}
+
+impl Input for Debugger
+{
+    fn poll(& mut self, game: &Game, _frame: &Frame, deadline: Instant) -> Result<Vec<Action>, String>
+    {
+        let actions = self.read_input(game, deadline).map_err(terminal_error)?;
+        self.keypad.frame();
+        Ok(actions)
+    }
+
+    fn keypad(& mut self, keys: &mut [u8; 16])
+    {
+        self.keypad.apply(keys);
+    }
+}
+
+// debugs until escape and returns how the VM fared; only terminal errors end
+// it early
+pub fn run(game: &mut Game, options: &RunOptions) -> Result<(Exit, Result<(), Fault>), String>
+{
+    let _terminal = RawTerminal::enter().map_err(terminal_error)?;
+    let pc = game.vm.registers().pc;
+    let mut debugger = Debugger
+    {
+        out: io::stdout(),
+        lines: Lines::new(),
+        keypad: Keypad::new(),
+        glyphs: if options.braille { Glyphs::Braille } else { Glyphs::HalfBlocks },
+        fast_forward: false,
+        cursor: pc,
+        follow: false,
+        memory_top: pc as usize / BYTES_PER_ROW,
+        memory_rows: 8,
+        message: None,
+    };
+    frontend::run(game, options, &mut debugger)
+}
@@ -0,0 +1,148 @@
+// Streams every frame as uncompressed video, either YUV4MPEG2 or raw RGB24,
+// for piping into an external encoder. The beeper can be written next to it as
+// a WAV file with exactly 1/60 s of samples per frame, so the two stay in sync.
+
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::beeper::{Beeper, WavWriter, SAMPLE_RATE};
+use crate::palette::Palette;
+
+const FRAMES_PER_SECOND: u32 = 60;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VideoFormat
+{
+    Y4m,
+    Rgb24,
+}
+
+impl VideoFormat
+{
+    // "-" (stdout) and *.y4m get Y4M, which carries its own size and frame rate;
+    // everything else is headerless RGB24
+    pub fn from_path(path: &str) -> VideoFormat
+    {
+        if path == "-" || path.to_lowercase().ends_with(".y4m")
+        {
+            VideoFormat::Y4m
+        }
+        else
+        {
+            VideoFormat::Rgb24
+        }
+    }
+}
+
+pub struct VideoOut
+{
+    out: Box<dyn Write>,
+    format: VideoFormat,
+    width: usize,
+    height: usize,
+    scale: usize,
+    palette: Palette,
+
+    audio: Option<(WavWriter, Beeper)>,
+    samples: Vec<i16>,
+    frame: u64,
+}
+
+impl VideoOut
+{
+    // `volume` is the beeper's on the soundtrack, 0.0 to 1.0
+    pub fn create(path: &str, width: usize, height: usize, palette: &Palette, scale: usize,
+        audio_path: Option<&Path>, volume: f32) -> io::Result<VideoOut>
+    {
+        let format = VideoFormat::from_path(path);
+        let out: Box<dyn Write> = if path == "-"
+        {
+            Box::new(BufWriter::new(io::stdout()))
+        }
+        else
+        {
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+
+        let audio = match audio_path
+        {
+            Some(audio_path) => Some((WavWriter::create(audio_path)?, Beeper::new(volume))),
+            None => None,
+        };
+
+        let mut video = VideoOut
+        {
+            out,
+            format,
+            width,
+            height,
+            scale,
+            palette: *palette,
+            audio,
+            samples: Vec::new(),
+            frame: 0,
+        };
+
+        if format == VideoFormat::Y4m
+        {
+            writeln!(video.out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width * scale, height * scale, FRAMES_PER_SECOND)?;
+        }
+        Ok(video)
+    }
+
+    pub fn write_frame(& mut self, gfx: &[u8], sound_on: bool) -> io::Result<()>
+    {
+        let rgb = self.palette.render(gfx, self.width, self.height, self.scale);
+        match self.format
+        {
+            VideoFormat::Rgb24 => self.out.write_all(&rgb)?,
+            VideoFormat::Y4m =>
+            {
+                self.out.write_all(b"FRAME\n")?;
+
+                // planar 4:4:4, so every pixel gets one byte in each of the three planes
+                let yuv: Vec<[u8; 3]> = rgb.chunks(3).map(|p| rgb_to_yuv(p[0], p[1], p[2])).collect();
+                for plane in 0..3
+                {
+                    let bytes: Vec<u8> = yuv.iter().map(|p| p[plane]).collect();
+                    self.out.write_all(&bytes)?;
+                }
+            },
+        }
+
+        if let Some((ref mut wav, ref mut beeper)) = self.audio
+        {
+            // spread the samples so that frame n always ends at sample n * SAMPLE_RATE / 60
+            let end = (self.frame + 1) * SAMPLE_RATE as u64 / FRAMES_PER_SECOND as u64;
+            let start = self.frame * SAMPLE_RATE as u64 / FRAMES_PER_SECOND as u64;
+            self.samples.resize((end - start) as usize, 0);
+            beeper.synthesize(sound_on, &mut self.samples);
+            wav.write(&self.samples)?;
+        }
+
+        self.frame += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()>
+    {
+        self.out.flush()?;
+        if let Some((wav, _)) = self.audio
+        {
+            wav.finish()?;
+        }
+        Ok(())
+    }
+}
+
+// BT.601 studio range
+fn rgb_to_yuv(r: u8, g: u8, b: u8) -> [u8; 3]
+{
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    let y = 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0;
+    let u = 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0;
+    let v = 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0;
+    [y.round() as u8, u.round() as u8, v.round() as u8]
+}
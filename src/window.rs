@@ -0,0 +1,398 @@
+// The SDL frontend: the game in a window, with sound, gamepads, the on
+// screen display and the debug window. The launcher in launcher.rs shares
+// the window with it.
+
+use std::path::Path;
+use std::thread;
+use std::time::Instant;
+
+use sdl2::AudioSubsystem;
+use sdl2::EventPump;
+use sdl2::audio::{AudioCallback, AudioCVT, AudioDevice, AudioSpecDesired, AudioSpecWAV};
+use sdl2::controller::Button;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::PixelFormatEnum;
+
+use crate::cli::RunOptions;
+use crate::cpu::Fault;
+use crate::debug_window::DebugWindow;
+use crate::frontend::{self, Action, Audio, Display, Exit, Frame, Input};
+use crate::keymap::Keymap;
+use crate::osd::Osd;
+use crate::romdb::RomInfo;
+use crate::screen::{self, Screen};
+use crate::{Game, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+struct Sound {
+    data: Vec<u8>,
+    volume: f32,
+    pos: usize,
+}
+
+impl AudioCallback for Sound {
+    type Channel = u8;
+
+    fn callback(&mut self, out: &mut [u8]) {
+        for dst in out.iter_mut() {
+            *dst = (*self.data.get(self.pos).unwrap_or(&0) as f32 * self.volume) as u8;
+            self.pos += 1;
+        }
+    }
+}
+
+// keypad key for an SDL key; printable keys have their ascii code as keycode
+fn keypad_key(keymap: &Keymap, keycode: Keycode) -> Option<usize>
+{
+    match keycode as i32
+    {
+        code @ 0x20..=0x7E => keymap.key(code as u8 as char),
+        _ => None,
+    }
+}
+
+
+fn window_title(rom_info: &RomInfo) -> String
+{
+    match rom_info.title
+    {
+        Some(ref title) => format!("rusty-8 - {}", title),
+        None => "rusty-8".to_string(),
+    }
+}
+
+fn shift(keymod: Mod) -> bool
+{
+    keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD)
+}
+
+struct Window<'a>
+{
+    screen: &'a mut Screen,
+    options: &'a RunOptions,
+    event_pump: EventPump,
+    osd: Osd,
+    debug_window: Option<DebugWindow>,
+    keys: [u8; 16],
+    redraw: bool, // the window needs drawing even though the VM's screen didn't change
+
+    audio_subsystem: AudioSubsystem,
+    audio_device: Option<AudioDevice<Sound>>,
+    has_sound: bool,
+}
+
+impl Window<'_>
+{
+    fn open_debug_window(& mut self)
+    {
+        match DebugWindow::open(self.screen, self.options.load_address)
+        {
+            Ok(debug_window) => self.debug_window = Some(debug_window),
+            Err(e) => self.osd.error(&format!("failed to open the debug window: {}", e)),
+        }
+    }
+
+    fn draw_screen(& mut self, game: &Game) -> Result<(), String>
+    {
+        // the texture can't be kept next to the canvas it comes from, see
+        // debug_window.rs
+        let texture_creator = self.screen.canvas.texture_creator();
+        let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH,
+            SCREEN_HEIGHT).map_err(|e| e.to_string())?;
+        texture.with_lock(None, |buffer: &mut [u8], pitch: usize|
+        {
+            for y in 0..SCREEN_HEIGHT as usize
+            {
+                for x in 0..SCREEN_WIDTH as usize
+                {
+                    let offset: usize = y*pitch + x*3;
+                    let color = game.palette.color(game.vm.gfx[(y * SCREEN_WIDTH as usize) + x]);
+                    buffer[offset..offset + 3].copy_from_slice(&color);
+                }
+            }
+        })?;
+        let viewport = screen::draw_texture(&mut self.screen.canvas, &texture, SCREEN_WIDTH, SCREEN_HEIGHT,
+            self.options.scale_mode, game.palette.background)?;
+        self.osd.draw(&mut self.screen.canvas, &viewport, &game.palette);
+        self.screen.canvas.present();
+        Ok(())
+    }
+
+    fn play_beep(& mut self)
+    {
+        let desired_spec = AudioSpecDesired
+        {
+            freq: Some(44_100),
+            channels: Some(1), // mono
+            samples: None,
+        };
+
+        let volume = self.options.volume as f32 / 100.0;
+        let device = self.audio_subsystem.open_playback(None, &desired_spec, |spec|
+        {
+            let wav = AudioSpecWAV::load_wav("beep.wav").expect("could not load test WAV file");
+            let cvt = AudioCVT::new(wav.format, wav.channels, wav.freq, spec.format,
+                spec.channels, spec.freq).expect("could not convert WAV file");
+            let data = cvt.convert(wav.buffer().to_vec());
+
+            // initialize the audio callback
+            Sound
+            {
+                data,
+                volume,
+                pos: 0,
+            }
+        });
+
+        // start playback
+        match device
+        {
+            Ok(device) =>
+            {
+                device.resume();
+                self.audio_device = Some(device);
+            },
+            Err(e) => self.osd.error(&format!("failed to play the beep: {}", e)),
+        }
+    }
+}
+
+impl Display for Window<'_>
+{
+    fn loaded(& mut self, game: &Game)
+    {
+        self.screen.set_title(&window_title(&game.rom_info));
+    }
+
+    fn notify(& mut self, text: &str)
+    {
+        self.osd.notify(text);
+    }
+
+    fn error(& mut self, text: &str)
+    {
+        self.osd.error(text);
+    }
+
+    fn draw(& mut self, game: &Game, frame: &Frame) -> Result<(), String>
+    {
+        self.osd.count_frame(frame.emulated, frame.emulated as u64 * game.session.cycles_per_frame as u64);
+        self.osd.set_status(frame.status(game));
+
+        let osd_changed = self.osd.update();
+        if game.vm.draw_flag || osd_changed || self.redraw
+        {
+            self.draw_screen(game)?;
+            self.redraw = false;
+        }
+
+        // the game carries on without the debug window
+        if let Some(ref mut debug_window) = self.debug_window
+        {
+            if let Err(e) = debug_window.draw(&game.vm, game.session.frame, &game.palette)
+            {
+                self.osd.error(&format!("debug window failed, closing it: {}", e));
+                self.debug_window = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Audio for Window<'_>
+{
+    fn sound(& mut self, started: bool, _playing: bool)
+    {
+        if !started
+        {
+            return;
+        }
+        if self.has_sound
+        {
+            self.play_beep();
+        }
+        else
+        {
+            eprintln!("BEEP");
+        }
+    }
+}
+
+impl Input for Window<'_>
+{
+    fn poll(& mut self, game: &Game, _frame: &Frame, deadline: Instant) -> Result<Vec<Action>, String>
+    {
+        let now = Instant::now();
+        if deadline > now
+        {
+            thread::sleep(deadline - now);
+        }
+
+        let mut actions = Vec::new();
+        let debug_id = self.debug_window.as_ref().map(DebugWindow::window_id);
+        while let Some(event) = self.event_pump.poll_event()
+        {
+            let action = match event
+            {
+                Event::Quit { .. } => Action::Quit,
+
+                // with the debug window open, closing a window doesn't quit
+                Event::Window { win_event: WindowEvent::Close, window_id, .. } =>
+                {
+                    if Some(window_id) != debug_id
+                    {
+                        Action::Quit
+                    }
+                    else
+                    {
+                        self.debug_window = None;
+                        continue;
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } =>
+                {
+                    match self.debug_window
+                    {
+                        Some(_) => self.debug_window = None,
+                        None => self.open_debug_window(),
+                    }
+                    continue;
+                },
+                Event::MouseWheel { window_id, y, .. } if Some(window_id) == debug_id =>
+                {
+                    self.debug_window.as_mut().unwrap().scroll(-y);
+                    continue;
+                },
+                Event::KeyDown { keycode: Some(Keycode::PageUp), window_id, .. } if Some(window_id) == debug_id =>
+                {
+                    self.debug_window.as_mut().unwrap().page(-1);
+                    continue;
+                },
+                Event::KeyDown { keycode: Some(Keycode::PageDown), window_id, .. } if Some(window_id) == debug_id =>
+                {
+                    self.debug_window.as_mut().unwrap().page(1);
+                    continue;
+                },
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } |
+                Event::ControllerButtonDown { button: Button::Back, .. } => Action::Menu,
+
+                Event::ControllerDeviceAdded { which, .. } =>
+                {
+                    self.screen.add_controller(which);
+                    continue;
+                },
+
+                // alt+enter toggles between windowed and fullscreen
+                Event::KeyDown { keycode: Some(Keycode::Return), keymod, repeat: false, .. }
+                    if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) =>
+                {
+                    if let Err(e) = self.screen.toggle_fullscreen()
+                    {
+                        self.osd.error(&format!("failed to switch fullscreen: {}", e));
+                    }
+                    self.redraw = true;
+                    continue;
+                },
+
+                // F5 resets and reloads the rom, shift+F5 clears memory as well
+                Event::KeyDown { keycode: Some(Keycode::F5), keymod, repeat: false, .. } =>
+                {
+                    Action::Reset { hard: shift(keymod) }
+                },
+
+                // a rom dropped onto the window replaces the running one
+                Event::DropFile { filename, .. } => Action::Load(filename),
+
+                // F12 saves a screenshot at the window scale, shift+F12 at the native resolution
+                Event::KeyDown { keycode: Some(Keycode::F12), keymod, repeat: false, .. } =>
+                {
+                    Action::Screenshot { native: shift(keymod) }
+                },
+
+                // F10 starts and stops a gif recording, F11 turns tracing on and off
+                Event::KeyDown { keycode: Some(Keycode::F10), repeat: false, .. } => Action::Record,
+                Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } => Action::Trace,
+
+                // the window has to be redrawn with the new letterboxing
+                Event::Window { win_event: WindowEvent::SizeChanged(..), .. } |
+                Event::Window { win_event: WindowEvent::Exposed, .. } =>
+                {
+                    self.redraw = true;
+                    continue;
+                },
+
+                // the pause key stops and resumes the VM
+                Event::KeyDown { keycode: Some(Keycode::Pause), repeat: false, .. } |
+                Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } => Action::Pause,
+
+                // F7 steps through a paused game a frame at a time, shift+F7 an instruction at a time
+                Event::KeyDown { keycode: Some(Keycode::F7), keymod, .. } =>
+                {
+                    if shift(keymod) { Action::Step } else { Action::Advance }
+                },
+
+                // tab fast forwards while it is held, F8 switches slow motion
+                Event::KeyDown { keycode: Some(Keycode::Tab), .. } => Action::FastForward(true),
+                Event::KeyUp { keycode: Some(Keycode::Tab), .. } |
+                Event::Window { win_event: WindowEvent::FocusLost, .. } => Action::FastForward(false),
+                Event::KeyDown { keycode: Some(Keycode::F8), repeat: false, .. } => Action::SlowMotion,
+
+                // F3 shows the frame rate and speed
+                Event::KeyDown { keycode: Some(Keycode::F3), repeat: false, .. } =>
+                {
+                    self.osd.toggle_meter();
+                    continue;
+                },
+
+                Event::KeyDown { keycode: Some(keycode), .. } =>
+                {
+                    if let Some(k) = keypad_key(&game.keymap, keycode)
+                    {
+                        self.keys[k] = 1;
+                    }
+                    continue;
+                },
+                Event::KeyUp { keycode: Some(keycode), .. } =>
+                {
+                    if let Some(k) = keypad_key(&game.keymap, keycode)
+                    {
+                        self.keys[k] = 0;
+                    }
+                    continue;
+                },
+
+                _ => continue,
+            };
+            actions.push(action);
+        }
+        Ok(actions)
+    }
+
+    fn keypad(& mut self, keys: &mut [u8; 16])
+    {
+        keys.copy_from_slice(&self.keys);
+    }
+}
+
+// runs the game in the window until it's closed or left, see frontend::run
+pub fn run(game: &mut Game, options: &RunOptions, screen: &mut Screen) -> Result<(Exit, Result<(), Fault>), String>
+{
+    let mut window = Window
+    {
+        event_pump: screen.sdl.event_pump()?,
+        audio_subsystem: screen.sdl.audio()?,
+        audio_device: None,
+        has_sound: Path::new("beep.wav").exists(),
+        osd: Osd::new(),
+        debug_window: None,
+        keys: [0; 16],
+        redraw: true,
+        screen,
+        options,
+    };
+    if options.debug
+    {
+        window.open_debug_window();
+    }
+    frontend::run(game, options, &mut window)
+}